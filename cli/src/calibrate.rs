@@ -0,0 +1,296 @@
+use common::calibration::Calibration;
+use common::serial::SerialLink;
+use common::settings::{Variable, load_conf};
+use dialoguer::{Confirm, Input};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::io::ErrorKind::{InvalidData, InvalidInput, NotConnected};
+use std::io::Write;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+//Known reference points of the usual calibrations, as a description and the real value
+fn references(variable: Variable) -> Vec<(String, f32)> {
+    match variable {
+        Variable::SoilHumidity => vec![
+            (t!("calibrate.dry").to_string(), 0.0),
+            (t!("calibrate.wet").to_string(), 100.0),
+        ],
+        Variable::Ph => vec![
+            (t!("calibrate.buffer", ph = 4).to_string(), 4.0),
+            (t!("calibrate.buffer", ph = 7).to_string(), 7.0),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+//Ask the user for the real values when there are no known references
+fn ask_references() -> Result<Vec<(String, f32)>, Box<dyn Error>> {
+    let mut refs = Vec::new();
+    loop {
+        let value: String = Input::new()
+            .with_prompt(t!("calibrate.reference"))
+            .allow_empty(true)
+            .interact_text()?;
+        if value.trim().is_empty() {
+            if refs.is_empty() {
+                continue;
+            }
+            return Ok(refs);
+        }
+        match value.trim().parse::<f32>() {
+            Ok(real) => refs.push((t!("calibrate.value", value = real).to_string(), real)),
+            Err(_) => println!("{}", t!("calibrate.not_number")),
+        }
+    }
+}
+
+//Show the raw values live until the user presses enter, the result is the mean of the last ones
+fn measure(link: &mut SerialLink, variable: Variable) -> Result<f32, Box<dyn Error>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut line = String::new();
+        let _ = io::stdin().read_line(&mut line);
+        let _ = tx.send(());
+    });
+
+    let mut last = VecDeque::new();
+    while rx.try_recv().is_err() {
+        match link.request_reading() {
+            Ok(read) => match read.get(variable) {
+                Some(raw) => {
+                    last.push_back(raw);
+                    if last.len() > 5 {
+                        last.pop_front();
+                    }
+                    print!("\r{}    ", t!("calibrate.raw", value = raw));
+                }
+                None => print!("\r{}    ", t!("calibrate.no_value")),
+            },
+            Err(e) => print!("\r{}    ", t!("serial.input_error", error = e)),
+        }
+        io::stdout().flush()?;
+        thread::sleep(Duration::from_secs(1));
+    }
+    println!();
+
+    if last.is_empty() {
+        return Err(Box::new(io::Error::new(
+            NotConnected,
+            t!("calibrate.no_value"),
+        )));
+    }
+    Ok(last.iter().sum::<f32>() / last.len() as f32)
+}
+
+//Simplest profile going through the measured points, given as raw and real values
+fn build_profile(variable: Variable, points: &[(f32, f32)]) -> Calibration {
+    match points {
+        [(dry, _), (wet, _)] if variable == Variable::SoilHumidity => Calibration::TwoPoint {
+            dry: *dry,
+            wet: *wet,
+        },
+        [(raw, real)] => Calibration::Linear {
+            offset: real - raw,
+            scale: 1.0,
+        },
+        [(raw0, real0), (raw1, real1)] if raw0 != raw1 => {
+            let scale = (real1 - real0) / (raw1 - raw0);
+            Calibration::Linear {
+                offset: real0 - raw0 * scale,
+                scale,
+            }
+        }
+        _ => Calibration::Piecewise {
+            points: points.to_vec(),
+        },
+    }
+}
+
+//Set the profile of the variable in the settings, editing only its table so the comments and
+//layout of the rest stay as they were written. It goes in the zone when it's one of the listed
+//ones, the legacy zone uses the shared profiles
+fn set_profile(
+    content: &str,
+    zone: &str,
+    variable: Variable,
+    profile: &Calibration,
+) -> Result<String, Box<dyn Error>> {
+    let mut settings: DocumentMut = content.parse()?;
+    let mut written = toml::to_string(profile)?
+        .parse::<DocumentMut>()?
+        .as_table()
+        .clone();
+    written.set_implicit(false);
+    written.decor_mut().set_prefix("\n");
+
+    let listed = settings
+        .get_mut("zones")
+        .and_then(|zones| zones.as_array_of_tables_mut())
+        .and_then(|zones| {
+            zones
+                .iter_mut()
+                .find(|z| z.get("name").and_then(|n| n.as_str()) == Some(zone))
+        });
+    let table = match listed {
+        Some(listed) => listed,
+        None => settings.as_table_mut(),
+    };
+    let calibration = table.entry("calibration").or_insert_with(|| {
+        let mut profiles = Table::new();
+        profiles.set_implicit(true);
+        Item::Table(profiles)
+    });
+    match calibration {
+        Item::Table(profiles) => {
+            profiles.insert(variable.name(), Item::Table(written));
+        }
+        Item::Value(Value::InlineTable(profiles)) => {
+            profiles.insert(
+                variable.name(),
+                Value::InlineTable(written.into_inline_table()),
+            );
+        }
+        _ => {
+            return Err(Box::new(io::Error::new(
+                InvalidData,
+                t!("calibrate.not_table"),
+            )));
+        }
+    }
+
+    Ok(settings.to_string())
+}
+
+pub(super) fn calibrate(sensor: &str, zone: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let Some(variable) = Variable::ALL.into_iter().find(|v| v.name() == sensor) else {
+        let options = Variable::ALL.map(|v| v.name()).join(", ");
+        return Err(Box::new(io::Error::new(
+            InvalidInput,
+            t!("calibrate.unknown", sensor = sensor, options = options),
+        )));
+    };
+
+    //The service keeps the port busy while it's running
+    println!("{}", t!("calibrate.stop_service"));
+    let zone = load_conf()?.zone(zone)?;
+    let mut link = SerialLink::new(&zone.board, zone.physical_interface.sensors);
+    if !link.is_connected() {
+        return Err(Box::new(io::Error::new(
+            NotConnected,
+            t!("serial.unavailable"),
+        )));
+    }
+
+    let mut refs = references(variable);
+    if refs.is_empty() {
+        refs = ask_references()?;
+    }
+
+    let mut points = Vec::new();
+    for (description, real) in refs {
+        println!("{}", t!("calibrate.place", reference = description));
+        let raw = measure(&mut link, variable)?;
+        println!("{}", t!("calibrate.point", raw = raw, real = real));
+        points.push((raw, real));
+    }
+
+    let profile = build_profile(variable, &points);
+    println!(
+        "{}",
+        t!("calibrate.result", profile = format!("{:?}", profile))
+    );
+    if Confirm::new()
+        .with_prompt(t!("calibrate.save"))
+        .interact()?
+    {
+        let path = "/etc/cultiva/settings.toml";
+        let content = set_profile(&fs::read_to_string(path)?, &zone.name, variable, &profile)?;
+        fs::write(path, content)?;
+        println!("{}", t!("calibrate.saved"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calibrate::{build_profile, set_profile};
+    use common::calibration::Calibration;
+    use common::settings::{DEFAULT_ZONE, Variable};
+
+    #[test]
+    fn profiles_from_points() {
+        assert_eq!(
+            build_profile(Variable::SoilHumidity, &[(800.0, 0.0), (400.0, 100.0)]),
+            Calibration::TwoPoint {
+                dry: 800.0,
+                wet: 400.0
+            }
+        );
+        let ph = build_profile(Variable::Ph, &[(4.2, 4.0), (7.2, 7.0)]);
+        assert!((ph.apply(5.2) - 5.0).abs() < 0.001);
+        assert_eq!(
+            build_profile(Variable::Temperature, &[(20.5, 20.0)]).apply(30.5),
+            30.0
+        );
+    }
+
+    #[test]
+    fn profile_written() {
+        let content = "# Sent to the server\n[network]\nonline = false # For now\n";
+        let written = set_profile(
+            content,
+            DEFAULT_ZONE,
+            Variable::Ph,
+            &Calibration::Linear {
+                offset: 0.1,
+                scale: 1.0,
+            },
+        )
+        .unwrap();
+
+        assert!(written.starts_with(content));
+        let table: toml::Table = toml::from_str(&written).unwrap();
+        assert_eq!(table["calibration"]["ph"]["type"].as_str(), Some("Linear"));
+
+        //Profiles already written are kept, also when inline
+        let inline = "calibration = { ph = { type = \"Linear\", offset = 0.1, scale = 1.0 } }\n";
+        let written = set_profile(
+            inline,
+            DEFAULT_ZONE,
+            Variable::Ec,
+            &Calibration::Linear {
+                offset: 0.0,
+                scale: 2.0,
+            },
+        )
+        .unwrap();
+        let table: toml::Table = toml::from_str(&written).unwrap();
+        assert_eq!(table["calibration"]["ph"]["offset"].as_float(), Some(0.1));
+        assert_eq!(table["calibration"]["ec"]["scale"].as_float(), Some(2.0));
+
+        let zoned = "[[zones]]\nname = \"north\"\n[[zones]]\nname = \"south\"\n";
+        let written = set_profile(
+            zoned,
+            "north",
+            Variable::SoilHumidity,
+            &Calibration::TwoPoint {
+                dry: 800.0,
+                wet: 400.0,
+            },
+        )
+        .unwrap();
+        let table: toml::Table = toml::from_str(&written).unwrap();
+        assert!(table.get("calibration").is_none());
+        assert!(table["zones"][1].get("calibration").is_none());
+        assert_eq!(
+            table["zones"][0]["calibration"]["soil_humidity"]["dry"].as_float(),
+            Some(800.0)
+        );
+    }
+}
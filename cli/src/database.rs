@@ -0,0 +1,107 @@
+use chrono::{Duration, Local, Utc};
+use common::db_client::{Aggregate, Bucket, HistoryQuery, get_history, migrate, schema_status};
+use common::retention::{print_report, run};
+use common::settings::{Variable, load_conf};
+use std::error::Error;
+use std::io;
+use std::io::ErrorKind::InvalidInput;
+
+fn status() -> Result<(), Box<dyn Error>> {
+    let (current, latest) = schema_status()?;
+    println!(
+        "{}",
+        t!("database.status", current = current, latest = latest)
+    );
+    if current > latest {
+        println!("{}", t!("database.newer"));
+    } else if current < latest {
+        println!("{}", t!("database.pending", count = latest - current));
+    }
+
+    Ok(())
+}
+
+fn invalid(arg: &str) -> Box<dyn Error> {
+    Box::new(io::Error::new(InvalidInput, t!("arg_unknown", arg = arg)))
+}
+
+//Readings of the last hours, optionally grouped: db history <hours> [bucket] [aggregate] [zone]
+fn history(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let Some(hours) = args.first() else {
+        println!("{}", t!("database.usage"));
+        return Ok(());
+    };
+    let hours: i64 = hours.parse().map_err(|_| invalid(hours))?;
+    let bucket = match args.get(1).map(|a| a.as_str()) {
+        None | Some("none") => None,
+        Some("minute") => Some(Bucket::Minute),
+        Some("hour") => Some(Bucket::Hour),
+        Some("day") => Some(Bucket::Day),
+        Some(other) => return Err(invalid(other)),
+    };
+    let aggregate = match args.get(2).map(|a| a.as_str()) {
+        None | Some("avg") => Aggregate::Avg,
+        Some("min") => Aggregate::Min,
+        Some("max") => Aggregate::Max,
+        Some("last") => Aggregate::Last,
+        Some(other) => return Err(invalid(other)),
+    };
+    let zone = load_conf()?.zone(args.get(3).map(|z| z.as_str()))?;
+
+    let query = HistoryQuery {
+        from: Utc::now() - Duration::hours(hours),
+        to: None,
+        bucket,
+        aggregate,
+    };
+    for read in get_history(&zone.name, &query)? {
+        let values: Vec<String> = Variable::ALL
+            .iter()
+            .filter_map(|v| {
+                read.get(*v)
+                    .map(|value| format!("{}={:.2}", v.name(), value))
+            })
+            .collect();
+        let time = read
+            .timestamp
+            .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        println!("{}  {}", time, values.join(" "));
+    }
+
+    Ok(())
+}
+
+//Report what the retention policy removes, and remove it unless it's a dry run
+fn prune(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let dry_run = match args.first().map(|a| a.as_str()) {
+        None => false,
+        Some("--dry-run") => true,
+        Some(other) => return Err(invalid(other)),
+    };
+    print_report(&run(dry_run)?);
+    if dry_run {
+        println!("{}", t!("retention.dry_run"));
+    }
+
+    Ok(())
+}
+
+pub(super) fn database(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.first().map(|a| a.as_str()) {
+        Some("migrate") => {
+            let (from, to) = migrate()?;
+            if from < to {
+                println!("{}", t!("database.migrated", from = from, to = to));
+            } else {
+                println!("{}", t!("database.up_to_date", version = to));
+            }
+        }
+        Some("status") => status()?,
+        Some("history") => history(&args[1..])?,
+        Some("prune") => prune(&args[1..])?,
+        _ => println!("{}", t!("database.usage")),
+    }
+
+    Ok(())
+}
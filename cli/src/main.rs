@@ -9,6 +9,8 @@ use std::io;
 use std::io::ErrorKind::PermissionDenied;
 use sudo::RunningAs;
 
+mod calibrate;
+mod database;
 mod setup;
 mod shell;
 
@@ -29,10 +31,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
         println!("{}", t!("usage"));
     } else if args[1] == "configure" {
         sudo_or_error()?;
-        setup::setup().await?;
+        setup::setup(args.get(2).map(|z| z.as_str())).await?;
     } else if args[1] == "compile" {
         sudo_or_error()?;
-        setup::compile_microcontroller()?;
+        setup::compile_microcontroller(args.get(2).map(|z| z.as_str()))?;
+    } else if args[1] == "db" {
+        sudo_or_error()?;
+        database::database(&args[2..])?;
+    } else if args[1] == "calibrate" {
+        sudo_or_error()?;
+        match args.get(2) {
+            Some(sensor) => calibrate::calibrate(sensor, args.get(3).map(|z| z.as_str()))?,
+            None => println!("{}", t!("usage")),
+        }
     } else {
         println!("{}", t!("arg_unknown", arg = args[1]));
         println!("{}", t!("usage"));
@@ -4,11 +4,14 @@ mod save_settings;
 use crate::setup::arduino_cli::install_core;
 use crate::setup::save_settings::save_conf;
 use common::credentials::save_jwt;
-use common::db_client::create_tables;
+use common::db_client::migrate;
 use common::rest_client::{Auth, Output, login_account, register_account};
-use common::settings::{Actuators, Board, IOFlags, Sensors, Settings, load_conf};
+use common::settings::{
+    ActuatorSpec, Actuators, Board, IO, IOFlags, Protocol, Sensors, Settings, Zone, load_conf,
+};
 use dialoguer::{Confirm, Input, MultiSelect, Password, Select};
 use git2::Repository;
+use std::collections::HashMap;
 use std::error::Error;
 use std::io;
 use std::io::ErrorKind::Interrupted;
@@ -90,8 +93,8 @@ async fn login_loop() -> Result<(), Box<dyn Error>> {
     }
 }
 
-pub(super) fn compile_microcontroller() -> Result<(), Box<dyn Error>> {
-    let config = load_conf()?;
+pub(super) fn compile_microcontroller(zone: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let zone = load_conf()?.zone(zone)?;
 
     //Clone microcontroller source code repo
     println!("{}", t!("board.source_code"));
@@ -104,30 +107,47 @@ pub(super) fn compile_microcontroller() -> Result<(), Box<dyn Error>> {
     }
     Repository::clone(url, "/var/lib/cultiva/cultiva-microcontroller")?;
 
-    println!("{}", t!("board.compile", core = config.board.name));
-    let flags: IOFlags = config.physical_interface.into();
+    println!("{}", t!("board.compile", core = zone.board.name));
+    let flags: IOFlags = zone.physical_interface.into();
 
     arduino_cli::compile_sketch(
-        &config.board.name,
+        &zone.board.name,
         flags.sensors_flag,
         flags.actuators_flag,
         flags.inverted_flag,
     )?;
-    arduino_cli::upload_sketch(&config.board.name, &config.board.port)?;
+    arduino_cli::upload_sketch(&zone.board.name, &zone.board.port)?;
 
     Ok(())
 }
 
-pub(super) async fn setup() -> Result<(), Box<dyn Error>> {
-    println!("{}", t!("setup_ini"));
-
-    if load_conf().is_ok() && !Confirm::new().with_prompt(t!("config.found")).interact()? {
-        //User canceled setup, early exit
-        return Ok(());
+//Replace the zone with that name or add it. A board configured before zones existed is listed as
+//the legacy zone first, otherwise it would stop being used once another zone is listed
+fn set_zone(settings: &mut Settings, name: &str, physical_interface: IO, board: Board) {
+    if settings.zones.is_empty()
+        && !(settings.board.name.is_empty() && settings.board.port.is_empty())
+    {
+        settings.zones = settings.zones();
+        settings.physical_interface = IO::default();
+        settings.board = Board::default();
+        settings.control = HashMap::new();
     }
 
-    let mut configuration = Settings::new();
+    match settings.zones.iter_mut().find(|z| z.name == name) {
+        Some(zone) => {
+            zone.physical_interface = physical_interface;
+            zone.board = board;
+        }
+        None => settings.zones.push(Zone {
+            name: name.to_string(),
+            board,
+            physical_interface,
+            ..Default::default()
+        }),
+    }
+}
 
+async fn set_network(configuration: &mut Settings) -> Result<(), Box<dyn Error>> {
     //Confirm selection loop
     loop {
         let online = Select::new()
@@ -158,6 +178,36 @@ pub(super) async fn setup() -> Result<(), Box<dyn Error>> {
         }
     }
 
+    Ok(())
+}
+
+//Without a zone the whole configuration is written again for a single board. With one, only that
+//zone is added or replaced in the current configuration
+pub(super) async fn setup(zone: Option<&str>) -> Result<(), Box<dyn Error>> {
+    println!("{}", t!("setup_ini"));
+
+    let mut configuration = match load_conf() {
+        Ok(settings) if zone.is_some() => settings,
+        Ok(_) if !Confirm::new().with_prompt(t!("config.found")).interact()? => {
+            //User canceled setup, early exit
+            return Ok(());
+        }
+        _ => {
+            let mut settings = Settings::new();
+            set_network(&mut settings).await?;
+            settings
+        }
+    };
+    if let Some(name) = zone
+        && configuration.zones().iter().any(|z| z.name == name)
+        && !Confirm::new()
+            .with_prompt(t!("zones.found", zone = name))
+            .interact()?
+    {
+        return Ok(());
+    }
+
+    let mut physical_interface = IO::default();
     let sensors = MultiSelect::new()
         .with_prompt(t!("sensors.set_sensors"))
         .items(vec![
@@ -168,9 +218,14 @@ pub(super) async fn setup() -> Result<(), Box<dyn Error>> {
             t!("sensors.lumin"),
             t!("sensors.co2"),
             t!("sensors.ph"),
+            t!("sensors.ec"),
+            t!("sensors.tank"),
+            t!("sensors.soil_therm"),
+            t!("sensors.baro"),
+            t!("sensors.leaf"),
         ])
         .interact()?;
-    configuration.physical_interface.sensors = sensors
+    physical_interface.sensors = sensors
         .iter()
         .map(|val| val.try_into())
         .collect::<Result<Vec<Sensors>, io::Error>>()?;
@@ -181,25 +236,29 @@ pub(super) async fn setup() -> Result<(), Box<dyn Error>> {
         t!("actuators.light"),
         t!("actuators.uv"),
         t!("actuators.shade"),
+        t!("actuators.fan"),
+        t!("actuators.humidifier"),
+        t!("actuators.dehumidifier"),
+        t!("actuators.co2"),
+        t!("actuators.nutrient"),
+        t!("actuators.ph_up"),
+        t!("actuators.ph_down"),
     ]);
 
     let actuators = act_items
         .clone()
         .with_prompt(t!("actuators.set_act"))
         .interact()?;
-    configuration.physical_interface.actuators = actuators
-        .iter()
-        .map(|val| val.try_into())
-        .collect::<Result<Vec<Actuators>, io::Error>>()?;
-
     //Invert output of certain actuators with this setting
     let invert = act_items
         .with_prompt(t!("actuators.set_invert"))
         .interact()?;
-    configuration.physical_interface.inverted = invert
+    physical_interface.actuators = actuators
         .iter()
-        .map(|val| val.try_into())
-        .collect::<Result<Vec<Actuators>, io::Error>>()?;
+        .map(|val| {
+            Actuators::try_from(val).map(|kind| ActuatorSpec::new(kind, invert.contains(val)))
+        })
+        .collect::<Result<Vec<ActuatorSpec>, io::Error>>()?;
 
     if !Confirm::new()
         .with_prompt(t!("board.download"))
@@ -210,48 +269,102 @@ pub(super) async fn setup() -> Result<(), Box<dyn Error>> {
     arduino_cli::install_arduino_cli()?;
 
     //Enter manual mode if the arduino board wasn't detected
-    configuration.board =
-        arduino_cli::get_board().or_else(|e| -> Result<Board, Box<dyn Error>> {
-            eprintln!("{}", e);
-            println!("{}", t!("board.manual_set.prompt"));
-            loop {
-                let port: String = Input::new()
-                    .with_prompt(t!("board.manual_set.port"))
-                    .interact_text()?;
-
-                if !port.starts_with("/dev/") {
-                    eprintln!("{}", t!("board.manual_set.invalid_input"));
-                    continue;
-                }
+    let board = arduino_cli::get_board().or_else(|e| -> Result<Board, Box<dyn Error>> {
+        eprintln!("{}", e);
+        println!("{}", t!("board.manual_set.prompt"));
+        loop {
+            let port: String = Input::new()
+                .with_prompt(t!("board.manual_set.port"))
+                .interact_text()?;
 
-                let name: String = Input::new()
-                    .with_prompt(t!("board.manual_set.board"))
-                    .interact()?;
+            if !port.starts_with("/dev/") {
+                eprintln!("{}", t!("board.manual_set.invalid_input"));
+                continue;
+            }
 
-                let Some(core) = name.rsplit_once(":") else {
-                    eprintln!("{}", t!("board.manual_set.invalid_input"));
-                    continue;
-                };
-                install_core(core.0)?;
+            let name: String = Input::new()
+                .with_prompt(t!("board.manual_set.board"))
+                .interact()?;
 
-                return Ok(Board { port, name });
-            }
-        })?;
+            let Some(core) = name.rsplit_once(":") else {
+                eprintln!("{}", t!("board.manual_set.invalid_input"));
+                continue;
+            };
+            install_core(core.0)?;
+
+            return Ok(Board {
+                port,
+                name,
+                protocol: Protocol::Framed,
+                ..Default::default()
+            });
+        }
+    })?;
+
+    match zone {
+        Some(name) => set_zone(&mut configuration, name, physical_interface, board),
+        None => {
+            configuration.physical_interface = physical_interface;
+            configuration.board = board;
+        }
+    }
 
     println!("{}", t!("config.saving"));
     save_conf(configuration)?;
 
     println!("{}", t!("db_setup"));
-    create_tables()?;
+    migrate()?;
 
     if Confirm::new()
         .with_prompt(t!("board.compile_prompt"))
         .interact()?
     {
-        compile_microcontroller()?;
+        compile_microcontroller(zone)?;
     }
 
     println!("{}", t!("setup_complete"));
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::setup::set_zone;
+    use common::settings::{ActuatorSpec, Actuators, Board, DEFAULT_ZONE, IO, Sensors, Settings};
+
+    fn board(port: &str) -> Board {
+        Board {
+            name: "arduino:avr:uno".to_string(),
+            port: port.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn zones_configured() {
+        let mut settings = Settings {
+            board: board("/dev/ttyACM0"),
+            physical_interface: IO {
+                sensors: vec![Sensors::DHT11],
+                actuators: vec![ActuatorSpec::new(Actuators::Heater, false)],
+            },
+            ..Default::default()
+        };
+
+        //The legacy board is kept as its own zone
+        set_zone(&mut settings, "north", IO::default(), board("/dev/ttyACM1"));
+        let names: Vec<String> = settings.zones.iter().map(|z| z.name.clone()).collect();
+        assert_eq!(names, [DEFAULT_ZONE, "north"]);
+        assert!(settings.zones[0].physical_interface.sensors == [Sensors::DHT11]);
+        assert!(settings.physical_interface.actuators.is_empty());
+
+        set_zone(&mut settings, "north", IO::default(), board("/dev/ttyUSB0"));
+        assert_eq!(settings.zones.len(), 2);
+        assert_eq!(settings.zones[1].board.port, "/dev/ttyUSB0");
+
+        //Nothing to keep in new settings
+        let mut settings = Settings::default();
+        set_zone(&mut settings, "south", IO::default(), board("/dev/ttyACM0"));
+        assert_eq!(settings.zones.len(), 1);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::shell::{display_output, execute_command};
-use common::settings::Board;
+use common::settings::{Board, Protocol};
 use std::error::Error;
 use std::fs::{exists, read_to_string};
 use std::io::Error as IoError;
@@ -85,6 +85,8 @@ pub(super) fn get_board() -> Result<Board, Box<dyn Error>> {
     let curr_board = Board {
         name: full_board.to_string(),
         port: attributes[0].to_string(),
+        protocol: Protocol::Framed,
+        ..Default::default()
     };
 
     Ok(curr_board)
@@ -92,9 +94,9 @@ pub(super) fn get_board() -> Result<Board, Box<dyn Error>> {
 
 pub(super) fn compile_sketch(
     board_name: &str,
-    sensors_flag: u8,
-    actuators_flag: u8,
-    invert_flag: u8,
+    sensors_flag: u32,
+    actuators_flag: u32,
+    invert_flag: u32,
 ) -> Result<(), IoError> {
     let path = get_code_path(board_name);
 
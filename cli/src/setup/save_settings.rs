@@ -11,19 +11,25 @@ pub(super) fn save_conf(config: Settings) -> Result<(), Box<dyn Error>> {
 
 #[test]
 fn test_save() -> Result<(), Box<dyn Error>> {
-    use common::settings::{Actuators, Board, IO, NetConf, Sensors};
+    use common::settings::{ActuatorSpec, Actuators, Board, IO, NetConf, Protocol, Sensors};
 
     let test_settings = Settings {
         network: NetConf { online: true },
         physical_interface: IO {
             sensors: vec![Sensors::Thermometer, Sensors::Hygrometer, Sensors::Co2],
-            actuators: vec![Actuators::Irrigator, Actuators::Heater, Actuators::Lighting],
-            inverted: vec![Actuators::Irrigator],
+            actuators: vec![
+                ActuatorSpec::new(Actuators::Irrigator, true),
+                ActuatorSpec::new(Actuators::Heater, false),
+                ActuatorSpec::new(Actuators::Lighting, false),
+            ],
         },
         board: Board {
             name: "arduino:avr:uno".to_string(),
             port: "/dev/ttyACM0".to_string(),
+            protocol: Protocol::Framed,
+            ..Default::default()
         },
+        ..Default::default()
     };
     save_conf(test_settings)?;
 
@@ -0,0 +1,132 @@
+use crate::db_client::Reading;
+use crate::settings::Variable;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+//Turns what the sensor reports into the real value, raw readings are kept to recalibrate later
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type")]
+pub enum Calibration {
+    //Value times the scale plus the offset
+    Linear {
+        #[serde(default)]
+        offset: f32,
+        #[serde(default = "unit_scale")]
+        scale: f32,
+    },
+    //Pairs of raw and real values, interpolated between them and extrapolated past the ends
+    Piecewise {
+        points: Vec<(f32, f32)>,
+    },
+    //Raw values of the sensor in dry air and in water, mapped to 0% and 100%
+    TwoPoint {
+        dry: f32,
+        wet: f32,
+    },
+}
+
+fn unit_scale() -> f32 {
+    1.0
+}
+
+fn interpolate((x0, y0): (f32, f32), (x1, y1): (f32, f32), x: f32) -> f32 {
+    if x1 == x0 {
+        return y0;
+    }
+    y0 + (x - x0) * (y1 - y0) / (x1 - x0)
+}
+
+impl Calibration {
+    pub fn apply(&self, raw: f32) -> f32 {
+        match self {
+            Calibration::Linear { offset, scale } => raw * scale + offset,
+            Calibration::Piecewise { points } => {
+                let mut sorted = points.clone();
+                sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+                match sorted.len() {
+                    0 => raw,
+                    //A single point is just an offset
+                    1 => raw - sorted[0].0 + sorted[0].1,
+                    n => {
+                        //Segment containing the value, or the closest one at the ends
+                        let i = sorted[1..n - 1].iter().take_while(|p| p.0 < raw).count();
+                        interpolate(sorted[i], sorted[i + 1], raw)
+                    }
+                }
+            }
+            Calibration::TwoPoint { dry, wet } => {
+                interpolate((*dry, 0.0), (*wet, 100.0), raw).clamp(0.0, 100.0)
+            }
+        }
+    }
+}
+
+//Apply the profile of every variable that has one, keyed by the variable name, the same used
+//for the reading fields
+pub fn calibrate(read: &mut Reading, profiles: &HashMap<String, Calibration>) {
+    for variable in Variable::ALL {
+        let Some(profile) = profiles.get(variable.name()) else {
+            continue;
+        };
+        if let Some(raw) = read.get(variable) {
+            read.raw.insert(variable.name().to_string(), raw);
+            read.set(variable, Some(profile.apply(raw)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calibration::{Calibration, calibrate};
+    use crate::db_client::Reading;
+    use std::collections::HashMap;
+
+    #[test]
+    fn profiles() {
+        let linear = Calibration::Linear {
+            offset: -1.5,
+            scale: 2.0,
+        };
+        assert_eq!(linear.apply(10.0), 18.5);
+
+        let piecewise = Calibration::Piecewise {
+            points: vec![(10.0, 100.0), (0.0, 0.0), (20.0, 150.0)],
+        };
+        assert_eq!(piecewise.apply(5.0), 50.0);
+        assert_eq!(piecewise.apply(15.0), 125.0);
+        //Past the ends the closest segment is extended
+        assert_eq!(piecewise.apply(-1.0), -10.0);
+        assert_eq!(piecewise.apply(30.0), 200.0);
+
+        //Capacitive soil sensors read lower the wetter they are
+        let soil = Calibration::TwoPoint {
+            dry: 800.0,
+            wet: 400.0,
+        };
+        assert_eq!(soil.apply(600.0), 50.0);
+        assert_eq!(soil.apply(900.0), 0.0);
+        assert_eq!(soil.apply(300.0), 100.0);
+    }
+
+    #[test]
+    fn raw_values_kept() {
+        let profiles = HashMap::from([(
+            "temperature".to_string(),
+            Calibration::Linear {
+                offset: 0.5,
+                scale: 1.0,
+            },
+        )]);
+        let mut read = Reading {
+            temperature: Some(20.0),
+            ph: Some(6.5),
+            ..Default::default()
+        };
+
+        calibrate(&mut read, &profiles);
+        assert_eq!(read.temperature, Some(20.5));
+        assert_eq!(read.raw.get("temperature"), Some(&20.0));
+        assert_eq!(read.ph, Some(6.5));
+        assert!(!read.raw.contains_key("ph"));
+    }
+}
@@ -1,19 +1,22 @@
+use crate::settings::{write_zone_file, zone_path};
 use config::{Config, ConfigError, File};
 use std::collections::HashMap;
 use std::error::Error;
 use std::io::ErrorKind::UnexpectedEof;
 use std::{fs, io};
 
-pub fn set_context(context: HashMap<String, String>) -> Result<(), Box<dyn Error>> {
+const CONTEXT_FILE: &str = "/etc/cultiva/context.toml";
+
+pub fn set_context(zone: &str, context: HashMap<String, String>) -> Result<(), Box<dyn Error>> {
     let content = toml::to_string(&context)?;
-    fs::write("/etc/cultiva/context.toml", content)?;
+    write_zone_file(zone, CONTEXT_FILE, content)?;
 
     Ok(())
 }
 
-pub fn get_context() -> Result<HashMap<String, String>, ConfigError> {
-    let path = "/etc/cultiva/context.toml";
-    match fs::exists(path) {
+pub fn get_context(zone: &str) -> Result<HashMap<String, String>, ConfigError> {
+    let path = zone_path(zone, CONTEXT_FILE);
+    match fs::exists(&path) {
         Ok(exists) => {
             if !exists {
                 return Err(ConfigError::NotFound(t!("context.load_err").to_string()));
@@ -23,7 +26,7 @@ pub fn get_context() -> Result<HashMap<String, String>, ConfigError> {
     }
 
     let context = Config::builder()
-        .add_source(File::with_name(path))
+        .add_source(File::with_name(&path))
         .build()?
         .try_deserialize::<HashMap<String, String>>()?;
 
@@ -1,47 +1,294 @@
-use chrono::{DateTime, Local};
+use crate::settings::{Retention, Variable};
+use chrono::{DateTime, Duration, Local, TimeZone, Timelike, Utc};
 use rusqlite::{Connection, Error, Row};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration as StdDuration;
 
 #[derive(Debug, Default, Serialize)]
 pub struct Reading {
-    pub timestamp: Option<DateTime<Local>>,
+    //Stored in milliseconds, converted to local time only when shown
+    pub timestamp: Option<DateTime<Utc>>,
     pub temperature: Option<f32>,
     pub air_humidity: Option<f32>,
     pub soil_humidity: Option<f32>,
     pub luminosity: Option<f32>,
     pub air_quality: Option<f32>,
     pub ph: Option<f32>,
+    //mS/cm
+    pub ec: Option<f32>,
+    //Percentage of the reservoir
+    pub tank_level: Option<f32>,
+    pub soil_temperature: Option<f32>,
+    //hPa
+    pub pressure: Option<f32>,
+    //Percentage of the leaf surface that's wet
+    pub leaf_wetness: Option<f32>,
+    //Values as the sensors gave them, only for the calibrated variables
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub raw: HashMap<String, f32>,
 }
 
 impl Reading {
     pub fn new() -> Self {
         Default::default()
     }
+
+    pub fn get(&self, variable: Variable) -> Option<f32> {
+        match variable {
+            Variable::Temperature => self.temperature,
+            Variable::AirHumidity => self.air_humidity,
+            Variable::SoilHumidity => self.soil_humidity,
+            Variable::Luminosity => self.luminosity,
+            Variable::AirQuality => self.air_quality,
+            Variable::Ph => self.ph,
+            Variable::Ec => self.ec,
+            Variable::TankLevel => self.tank_level,
+            Variable::SoilTemperature => self.soil_temperature,
+            Variable::Pressure => self.pressure,
+            Variable::LeafWetness => self.leaf_wetness,
+        }
+    }
+
+    pub fn set(&mut self, variable: Variable, value: Option<f32>) {
+        let field = match variable {
+            Variable::Temperature => &mut self.temperature,
+            Variable::AirHumidity => &mut self.air_humidity,
+            Variable::SoilHumidity => &mut self.soil_humidity,
+            Variable::Luminosity => &mut self.luminosity,
+            Variable::AirQuality => &mut self.air_quality,
+            Variable::Ph => &mut self.ph,
+            Variable::Ec => &mut self.ec,
+            Variable::TankLevel => &mut self.tank_level,
+            Variable::SoilTemperature => &mut self.soil_temperature,
+            Variable::Pressure => &mut self.pressure,
+            Variable::LeafWetness => &mut self.leaf_wetness,
+        };
+        *field = value;
+    }
+}
+
+//Length of the intervals history readings are grouped in, in the time zone they're shown in
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Minute,
+    Hour,
+    Day,
+}
+
+//How the values of a bucket are combined, for every variable on its own
+#[derive(Deserialize, Serialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+    Min,
+    Max,
+    #[default]
+    Avg,
+    Last,
+}
+
+//Readings between two times, one per bucket when there's one
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct HistoryQuery {
+    pub from: DateTime<Utc>,
+    //Until now when missing
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub bucket: Option<Bucket>,
+    #[serde(default)]
+    pub aggregate: Aggregate,
+}
+
+impl Bucket {
+    fn start<Tz: TimeZone>(&self, time: DateTime<Utc>, tz: &Tz) -> DateTime<Utc> {
+        let naive = time.with_timezone(tz).naive_local();
+        let truncated = match self {
+            Bucket::Minute => naive.with_second(0),
+            Bucket::Hour => naive.with_second(0).and_then(|t| t.with_minute(0)),
+            Bucket::Day => naive.date().and_hms_opt(0, 0, 0),
+        }
+        .and_then(|t| t.with_nanosecond(0))
+        .unwrap_or(naive);
+
+        //Times skipped by a daylight saving change keep their own bucket
+        tz.from_local_datetime(&truncated)
+            .earliest()
+            .map(|start| start.with_timezone(&Utc))
+            .unwrap_or(time)
+    }
+}
+
+//Group readings sorted from oldest to newest, the timestamp of each group is the start of its bucket
+fn aggregate<Tz: TimeZone>(
+    readings: Vec<Reading>,
+    bucket: Bucket,
+    aggregate: Aggregate,
+    tz: &Tz,
+) -> Vec<Reading> {
+    let mut groups: Vec<(DateTime<Utc>, Vec<Reading>)> = Vec::new();
+    for read in readings {
+        let Some(time) = read.timestamp else {
+            continue;
+        };
+        let start = bucket.start(time, tz);
+        match groups.last_mut() {
+            Some((last, group)) if *last == start => group.push(read),
+            _ => groups.push((start, vec![read])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(start, group)| {
+            let mut combined = Reading {
+                timestamp: Some(start),
+                ..Default::default()
+            };
+            for variable in Variable::ALL {
+                let values: Vec<f32> = group.iter().filter_map(|r| r.get(variable)).collect();
+                let value = match aggregate {
+                    Aggregate::Min => values.iter().copied().reduce(f32::min),
+                    Aggregate::Max => values.iter().copied().reduce(f32::max),
+                    Aggregate::Avg if !values.is_empty() => {
+                        Some(values.iter().sum::<f32>() / values.len() as f32)
+                    }
+                    Aggregate::Avg => None,
+                    Aggregate::Last => values.last().copied(),
+                };
+                combined.set(variable, value);
+            }
+            combined
+        })
+        .collect()
 }
 
-fn get_connection() -> rusqlite::Result<Connection, Error> {
-    let path = "/var/lib/cultiva/readings.db3";
-    let db = Connection::open(path)?;
+fn query_history<Tz: TimeZone>(
+    connection: &Connection,
+    zone: &str,
+    query: &HistoryQuery,
+    tz: &Tz,
+) -> Result<Vec<Reading>, Error> {
+    let to = query.to.unwrap_or(Utc::now());
+    //The retention policy leaves each time covered by a single table, readings past its window
+    //come back as the hourly or daily averages they were rolled into
+    let averages = format!("time_ms, {}, NULL", VARIABLE_COLUMNS.join(", "));
+    let range = format!("{} AND time_ms >= ?2 AND time_ms <= ?3", ZONE_FILTER);
+    let mut stmt = connection.prepare_cached(&format!(
+        "SELECT {columns}, id FROM readings WHERE {range}
+        UNION ALL SELECT {averages}, 0 FROM readings_hourly WHERE {range}
+        UNION ALL SELECT {averages}, 0 FROM readings_daily WHERE {range}
+        ORDER BY time_ms ASC, id ASC",
+        columns = READING_COLUMNS
+    ))?;
+    let readings = stmt
+        .query_map(
+            (zone, query.from.timestamp_millis(), to.timestamp_millis()),
+            parse_reading,
+        )?
+        .collect::<Result<Vec<Reading>, Error>>()?;
+
+    Ok(match query.bucket {
+        Some(bucket) => aggregate(readings, bucket, query.aggregate, tz),
+        None => readings,
+    })
+}
+
+const DB_PATH: &str = "/var/lib/cultiva/readings.db3";
 
-    Ok(db)
+//Shared by every thread of the process, opened on first use. Statements are serialized here and
+//other processes (the cli) wait on the busy timeout instead of failing
+static CONNECTION: Mutex<Option<Connection>> = Mutex::new(None);
+
+fn open_connection() -> Result<Connection, Error> {
+    let connection = Connection::open(DB_PATH)?;
+    //Readers don't block the writer, and fewer syncs are easier on the SD card
+    connection
+        .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    connection.pragma_update(None, "synchronous", "NORMAL")?;
+    connection.busy_timeout(StdDuration::from_secs(5))?;
+
+    Ok(connection)
+}
+
+fn with_connection<T>(action: impl FnOnce(&Connection) -> Result<T, Error>) -> Result<T, Error> {
+    //A panic while holding the lock doesn't leave the connection unusable
+    let mut guard = CONNECTION.lock().unwrap_or_else(|e| e.into_inner());
+    let connection = match guard.as_ref() {
+        Some(connection) => connection,
+        //Not stored when opening fails, so the next call tries again
+        None => guard.insert(open_connection()?),
+    };
+    action(connection)
 }
 
+//Columns in the order parse_reading expects them
+const READING_COLUMNS: &str = "time_ms, temperature, air_hum, soil_hum, light, air_quality, ph, \
+    ec, tank_level, soil_temp, pressure, leaf_wetness, raw";
+
+//Columns added after the table was first released, tables created by older versions lack them
+const ADDED_COLUMNS: [&str; 7] = [
+    "ec REAL UNSIGNED",
+    "tank_level REAL UNSIGNED",
+    "soil_temp REAL",
+    "pressure REAL UNSIGNED",
+    "leaf_wetness REAL UNSIGNED",
+    //JSON object with the uncalibrated values
+    "raw TEXT",
+    //Rows without one come from the legacy zone
+    "zone TEXT",
+];
+
+//Rows of a zone, those stored before zones existed were moved to the legacy one
+const ZONE_FILTER: &str = "zone = ?1";
+
 fn parse_reading(row: &Row) -> Result<Reading, Error> {
     Ok(Reading {
-        timestamp: row.get(0)?,
+        timestamp: row
+            .get::<_, Option<i64>>(0)?
+            .and_then(DateTime::from_timestamp_millis),
         temperature: row.get(1)?,
         air_humidity: row.get(2)?,
         soil_humidity: row.get(3)?,
         luminosity: row.get(4)?,
         air_quality: row.get(5)?,
         ph: row.get(6)?,
+        ec: row.get(7)?,
+        tank_level: row.get(8)?,
+        soil_temperature: row.get(9)?,
+        pressure: row.get(10)?,
+        leaf_wetness: row.get(11)?,
+        raw: row
+            .get::<_, Option<String>>(12)?
+            .and_then(|raw| serde_json::from_str(&raw).ok())
+            .unwrap_or_default(),
     })
 }
 
-// Public functions --------------------------------------------------------------------------------
-pub fn create_tables() -> Result<(), Error> {
-    let connection = get_connection()?;
+fn add_missing_columns(
+    connection: &Connection,
+    table: &str,
+    columns: &[&str],
+) -> Result<(), Error> {
+    let mut stmt = connection.prepare(&format!("PRAGMA table_info({})", table))?;
+    let existing = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<String>, Error>>()?;
+
+    for column in columns {
+        let name = column.split_whitespace().next().unwrap_or_default();
+        if !existing.iter().any(|e| e == name) {
+            connection.execute(&format!("ALTER TABLE {} ADD COLUMN {}", table, column), ())?;
+        }
+    }
 
+    Ok(())
+}
+
+//Tables as first released
+fn create_readings(connection: &Connection) -> Result<(), Error> {
     connection.execute(
         "CREATE TABLE IF NOT EXISTS readings (
             time_stamp  TIMESTAMP PRIMARY KEY DEFAULT CURRENT_TIMESTAMP,
@@ -58,58 +305,354 @@ pub fn create_tables() -> Result<(), Error> {
     Ok(())
 }
 
-pub fn insert_reading(values: Reading) -> Result<(), Error> {
-    let connection = get_connection()?;
+//Databases from before the migrations may have some of these already, so they're added only if
+//they're missing
+fn add_reading_columns(connection: &Connection) -> Result<(), Error> {
+    add_missing_columns(connection, "readings", &ADDED_COLUMNS)
+}
+
+fn create_events(connection: &Connection) -> Result<(), Error> {
     connection.execute(
-        "INSERT INTO readings (temperature, air_hum, soil_hum, light, air_quality, ph) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        (values.temperature, values.air_humidity, values.soil_humidity, values.luminosity, values.air_quality, values.ph),
+        "CREATE TABLE IF NOT EXISTS events (
+            time_stamp  TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            kind        TEXT NOT NULL,
+            detail      TEXT
+            )",
+        (),
     )?;
+    add_missing_columns(connection, "events", &["zone TEXT"])
+}
+
+//Columns of the variables, shared by the readings and the tables of averages
+const VARIABLE_COLUMNS: [&str; 11] = [
+    "temperature",
+    "air_hum",
+    "soil_hum",
+    "light",
+    "air_quality",
+    "ph",
+    "ec",
+    "tank_level",
+    "soil_temp",
+    "pressure",
+    "leaf_wetness",
+];
+
+//Averages of the readings older than the retention window, the timestamp is the start of the UTC
+//hour or day and samples the amount of readings behind each row
+fn create_averages(connection: &Connection) -> Result<(), Error> {
+    let columns = VARIABLE_COLUMNS.map(|c| format!("{} REAL", c)).join(", ");
+    for table in ["readings_hourly", "readings_daily"] {
+        connection.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    time_stamp  TIMESTAMP NOT NULL,
+                    zone        TEXT NOT NULL,
+                    samples     INTEGER NOT NULL,
+                    {},
+                    PRIMARY KEY (time_stamp, zone)
+                    )",
+                table, columns
+            ),
+            (),
+        )?;
+    }
 
     Ok(())
 }
 
-pub fn get_last_reading() -> Result<Reading, Error> {
-    let connection = get_connection()?;
+//The expressions matched ZONE_FILTER when these were released, convert_timestamps and
+//index_event_zones rebuilt both on the plain zone column later
+fn create_indexes(connection: &Connection) -> Result<(), Error> {
+    connection.execute_batch(
+        "CREATE INDEX IF NOT EXISTS readings_zone_time
+            ON readings (COALESCE(zone, 'default'), time_stamp);
+        CREATE INDEX IF NOT EXISTS events_zone_time
+            ON events (COALESCE(zone, 'default'), time_stamp);",
+    )
+}
 
-    let res = connection.query_one(
-        "SELECT * FROM readings ORDER BY time_stamp DESC LIMIT 1 ",
-        (),
-        parse_reading,
+//Rebuild the tables of readings with an integer key and UTC timestamps in milliseconds, the old
+//key had a resolution of one second and two readings in the same second collided. The stored
+//timestamps were UTC text written by CURRENT_TIMESTAMP
+fn convert_timestamps(connection: &Connection) -> Result<(), Error> {
+    let columns = VARIABLE_COLUMNS.join(", ");
+    let definitions = VARIABLE_COLUMNS.map(|c| format!("{} REAL", c)).join(", ");
+    let millis = "CAST(strftime('%s', time_stamp) AS INTEGER) * 1000";
+    connection.execute_batch(&format!(
+        "CREATE TABLE readings_new (
+            id          INTEGER PRIMARY KEY,
+            time_ms     INTEGER NOT NULL,
+            zone        TEXT NOT NULL DEFAULT 'default',
+            {definitions},
+            raw         TEXT
+            );
+        INSERT INTO readings_new (time_ms, zone, {columns}, raw)
+            SELECT {millis}, COALESCE(zone, 'default'), {columns}, raw
+            FROM readings WHERE time_stamp IS NOT NULL ORDER BY time_stamp;
+        DROP TABLE readings;
+        ALTER TABLE readings_new RENAME TO readings;
+        CREATE INDEX readings_zone_time ON readings (zone, time_ms);"
+    ))?;
+
+    for table in ["readings_hourly", "readings_daily"] {
+        connection.execute_batch(&format!(
+            "CREATE TABLE {table}_new (
+                time_ms     INTEGER NOT NULL,
+                zone        TEXT NOT NULL,
+                samples     INTEGER NOT NULL,
+                {definitions},
+                PRIMARY KEY (time_ms, zone)
+                );
+            INSERT INTO {table}_new (time_ms, zone, samples, {columns})
+                SELECT {millis}, zone, samples, {columns} FROM {table};
+            DROP TABLE {table};
+            ALTER TABLE {table}_new RENAME TO {table};"
+        ))?;
+    }
+
+    Ok(())
+}
+
+//Events stored before zones existed go to the legacy zone, like the readings did
+fn index_event_zones(connection: &Connection) -> Result<(), Error> {
+    connection.execute_batch(
+        "UPDATE events SET zone = 'default' WHERE zone IS NULL;
+        DROP INDEX IF EXISTS events_zone_time;
+        CREATE INDEX events_zone_time ON events (zone, time_stamp);",
+    )
+}
+
+type Migration = fn(&Connection) -> Result<(), Error>;
+
+//Every schema change in the order they were released, the version of a database is the amount of
+//them already applied (PRAGMA user_version). Never change or reorder them, add new ones at the end
+const MIGRATIONS: [Migration; 7] = [
+    create_readings,
+    add_reading_columns,
+    create_events,
+    create_averages,
+    create_indexes,
+    convert_timestamps,
+    index_event_zones,
+];
+
+fn schema_version(connection: &Connection) -> Result<u32, Error> {
+    connection.query_one("PRAGMA user_version", (), |row| row.get(0))
+}
+
+//Apply the pending migrations, each one along with its version so a failure leaves the database
+//at the last one that worked. Returns the versions before and after
+fn run_migrations(connection: &Connection) -> Result<(u32, u32), Error> {
+    let from = schema_version(connection)?;
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        let transaction = connection.unchecked_transaction()?;
+        migration(&transaction)?;
+        transaction.pragma_update(None, "user_version", version as u32 + 1)?;
+        transaction.commit()?;
+    }
+
+    Ok((from, schema_version(connection)?))
+}
+
+//Rows removed from each table by the retention policy
+#[derive(Debug, Default, PartialEq)]
+pub struct Pruned {
+    //Rolled into hourly averages
+    pub readings: usize,
+    //Rolled into daily averages
+    pub hourly: usize,
+    pub daily: usize,
+}
+
+const HOUR_MS: i64 = 3_600_000;
+const DAY_MS: i64 = 24 * HOUR_MS;
+
+//Cutoffs are aligned to the hour or day so a bucket is never rolled up from part of its rows
+fn cutoff(now: DateTime<Utc>, days: u32, step: i64) -> i64 {
+    let time = (now - Duration::days(days as i64)).timestamp_millis();
+    time - time.rem_euclid(step)
+}
+
+//Roll the readings past the window into hourly averages, old hourly ones into daily ones and
+//remove the oldest daily ones. A dry run reports the same counts but rolls everything back
+fn prune_readings(
+    connection: &Connection,
+    retention: &Retention,
+    now: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<Pruned, Error> {
+    let transaction = connection.unchecked_transaction()?;
+    let columns = VARIABLE_COLUMNS.join(", ");
+    //A bucket pruned before, like an hour split across two runs, keeps the samples it had
+    let merge = VARIABLE_COLUMNS
+        .map(|c| {
+            format!(
+                "{0} = CASE WHEN excluded.{0} IS NULL THEN {0} WHEN {0} IS NULL THEN excluded.{0}
+                ELSE ({0} * samples + excluded.{0} * excluded.samples) / (samples + excluded.samples) END",
+                c
+            )
+        })
+        .join(", ");
+    let upsert = format!(
+        "ON CONFLICT (time_ms, zone) DO UPDATE SET samples = samples + excluded.samples, {}",
+        merge
+    );
+
+    let averages = VARIABLE_COLUMNS.map(|c| format!("AVG({})", c)).join(", ");
+    let raw_cutoff = cutoff(now, retention.raw_days, HOUR_MS);
+    transaction.execute(
+        &format!(
+            "INSERT INTO readings_hourly (time_ms, zone, samples, {})
+            SELECT time_ms - time_ms % ?2, zone, COUNT(*), {}
+            FROM readings WHERE time_ms < ?1 GROUP BY 1, 2 {}",
+            columns, averages, upsert
+        ),
+        (raw_cutoff, HOUR_MS),
+    )?;
+    let readings = transaction.execute("DELETE FROM readings WHERE time_ms < ?1", (raw_cutoff,))?;
+
+    //Weighted by the readings behind each hour, an approximation for variables some readings lack
+    let weighted = VARIABLE_COLUMNS
+        .map(|c| {
+            format!(
+                "SUM({0} * samples) / SUM(CASE WHEN {0} IS NOT NULL THEN samples END)",
+                c
+            )
+        })
+        .join(", ");
+    let hourly_cutoff = cutoff(now, retention.hourly_days, DAY_MS);
+    transaction.execute(
+        &format!(
+            "INSERT INTO readings_daily (time_ms, zone, samples, {})
+            SELECT time_ms - time_ms % ?2, zone, SUM(samples), {}
+            FROM readings_hourly WHERE time_ms < ?1 GROUP BY 1, 2 {}",
+            columns, weighted, upsert
+        ),
+        (hourly_cutoff, DAY_MS),
+    )?;
+    let hourly = transaction.execute(
+        "DELETE FROM readings_hourly WHERE time_ms < ?1",
+        (hourly_cutoff,),
     )?;
-    Ok(res)
+
+    let daily = match retention.daily_days {
+        Some(days) => transaction.execute(
+            "DELETE FROM readings_daily WHERE time_ms < ?1",
+            (cutoff(now, days, DAY_MS),),
+        )?,
+        None => 0,
+    };
+
+    if dry_run {
+        transaction.rollback()?;
+    } else {
+        transaction.commit()?;
+    }
+    Ok(Pruned {
+        readings,
+        hourly,
+        daily,
+    })
+}
+
+// Public functions --------------------------------------------------------------------------------
+//Versions of the database and of the newest migration
+pub fn schema_status() -> Result<(u32, u32), Error> {
+    with_connection(|connection| Ok((schema_version(connection)?, MIGRATIONS.len() as u32)))
+}
+
+//Create or bring the database up to date, returns the versions before and after
+pub fn migrate() -> Result<(u32, u32), Error> {
+    with_connection(run_migrations)
+}
+
+pub fn insert_reading(zone: &str, values: Reading) -> Result<(), Error> {
+    let raw = if values.raw.is_empty() {
+        None
+    } else {
+        serde_json::to_string(&values.raw).ok()
+    };
+    let time_ms = values.timestamp.unwrap_or(Utc::now()).timestamp_millis();
+    with_connection(|connection| {
+        connection.prepare_cached(
+            "INSERT INTO readings (temperature, air_hum, soil_hum, light, air_quality, ph, ec, tank_level, soil_temp, pressure, leaf_wetness, raw, zone, time_ms) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+        )?.execute(
+            (values.temperature, values.air_humidity, values.soil_humidity, values.luminosity, values.air_quality, values.ph, values.ec, values.tank_level, values.soil_temperature, values.pressure, values.leaf_wetness, raw, zone, time_ms),
+        )?;
+
+        Ok(())
+    })
+}
+
+pub fn get_last_reading(zone: &str) -> Result<Reading, Error> {
+    with_connection(|connection| {
+        connection
+            .prepare_cached(&format!(
+                "SELECT {} FROM readings WHERE {} ORDER BY time_ms DESC, id DESC LIMIT 1",
+                READING_COLUMNS, ZONE_FILTER
+            ))?
+            .query_one((zone,), parse_reading)
+    })
 }
 
-pub fn get_readings(limit: u64) -> Result<Vec<Reading>, Error> {
+pub fn get_readings(zone: &str, limit: u64) -> Result<Vec<Reading>, Error> {
     if limit == 1 {
         //I suppose this is faster
-        return Ok(vec![get_last_reading()?]);
+        return Ok(vec![get_last_reading(zone)?]);
     }
-    let connection = get_connection()?;
 
-    let mut stmt = connection.prepare(&format!(
-        "SELECT * FROM readings ORDER BY time_stamp DESC LIMIT {}",
-        limit
-    ))?;
-    let res = stmt.query_map([], parse_reading)?;
-    let data: Result<Vec<Reading>, Error> = res.collect();
+    with_connection(|connection| {
+        let mut stmt = connection.prepare_cached(&format!(
+            "SELECT {} FROM readings WHERE {} ORDER BY time_ms DESC, id DESC LIMIT ?2",
+            READING_COLUMNS, ZONE_FILTER
+        ))?;
+        let res = stmt.query_map((zone, limit as i64), parse_reading)?;
+        res.collect()
+    })
+}
+
+//Oldest first, so they can be charted as they come. Buckets follow the local time of the device
+pub fn get_history(zone: &str, query: &HistoryQuery) -> Result<Vec<Reading>, Error> {
+    with_connection(|connection| query_history(connection, zone, query, &Local))
+}
 
-    data
+//Failsafe trips and other occurrences worth keeping next to the readings
+pub fn insert_event(zone: &str, kind: &str, detail: &str) -> Result<(), Error> {
+    with_connection(|connection| {
+        connection
+            .prepare_cached("INSERT INTO events (kind, detail, zone) VALUES (?1, ?2, ?3)")?
+            .execute((kind, detail, zone))?;
+
+        Ok(())
+    })
+}
+
+//Apply the retention policy to the readings, see prune_readings
+pub fn prune(retention: &Retention, dry_run: bool) -> Result<Pruned, Error> {
+    with_connection(|connection| prune_readings(connection, retention, Utc::now(), dry_run))
 }
 
 //Only for debug, remove all records
 pub fn delete_readings() -> Result<(), Error> {
-    let connection = get_connection()?;
-    connection.execute("DELETE FROM readings", ())?;
+    with_connection(|connection| {
+        connection.execute("DELETE FROM readings", ())?;
 
-    Ok(())
+        Ok(())
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use crate::db_client::{
-        Reading, create_tables, get_last_reading, get_readings, insert_reading,
+        ADDED_COLUMNS, Aggregate, Bucket, HistoryQuery, MIGRATIONS, Pruned, READING_COLUMNS,
+        Reading, ZONE_FILTER, add_missing_columns, aggregate, create_readings, get_last_reading,
+        get_readings, insert_reading, migrate, prune_readings, query_history, run_migrations,
+        schema_version,
     };
-    use rusqlite::Error;
+    use crate::settings::{DEFAULT_ZONE, Retention};
+    use chrono::{Duration as TimeDelta, FixedOffset, TimeZone, Utc};
+    use rusqlite::{Connection, Error};
     use std::thread::sleep;
     use std::time::Duration;
 
@@ -117,7 +660,7 @@ mod tests {
     fn create() -> Result<(), Error> {
         //Table creation ----------------------------------------------------------------------------
         println!("Creating database tables...");
-        create_tables()?;
+        migrate()?;
 
         Ok(())
     }
@@ -135,9 +678,10 @@ mod tests {
             luminosity: Some(100.0),
             air_quality: Some(100.0),
             ph: Some(8.5),
+            ..Default::default()
         };
 
-        insert_reading(test_read)?;
+        insert_reading(DEFAULT_ZONE, test_read)?;
 
         Ok(())
     }
@@ -154,16 +698,277 @@ mod tests {
             luminosity: Some(70.0),
             air_quality: Some(680.0),
             ph: Some(7.5),
+            ..Default::default()
         };
         sleep(Duration::from_secs(1));
-        insert_reading(test_read)?;
+        insert_reading(DEFAULT_ZONE, test_read)?;
 
-        let last = get_last_reading()?;
-        let all = get_readings(2)?;
+        let last = get_last_reading(DEFAULT_ZONE)?;
+        let all = get_readings(DEFAULT_ZONE, 2)?;
 
         println!("last: {:?}", last);
         println!("all: {:?}", all);
 
         Ok(())
     }
+
+    #[test]
+    fn added_columns() -> Result<(), Error> {
+        let connection = Connection::open_in_memory()?;
+        connection.execute("CREATE TABLE readings (time_stamp TIMESTAMP, ph REAL)", ())?;
+
+        add_missing_columns(&connection, "readings", &ADDED_COLUMNS)?;
+        //Running it again must not try to add them twice
+        add_missing_columns(&connection, "readings", &ADDED_COLUMNS)?;
+        connection.execute("INSERT INTO readings (tank_level) VALUES (?1)", (12.5,))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrations() -> Result<(), Error> {
+        let latest = MIGRATIONS.len() as u32;
+        let connection = Connection::open_in_memory()?;
+        assert_eq!(run_migrations(&connection)?, (0, latest));
+        assert_eq!(run_migrations(&connection)?, (latest, latest));
+
+        //Created before the migrations, with some of the later columns already added
+        let legacy = Connection::open_in_memory()?;
+        create_readings(&legacy)?;
+        legacy.execute("ALTER TABLE readings ADD COLUMN ec REAL", ())?;
+        legacy.execute(
+            "INSERT INTO readings (time_stamp, temperature) VALUES ('2026-01-10 08:30:15', 21.5)",
+            (),
+        )?;
+        run_migrations(&legacy)?;
+        assert_eq!(schema_version(&legacy)?, latest);
+
+        //Kept with a key, in the legacy zone and in milliseconds
+        let read = legacy.query_one(
+            &format!(
+                "SELECT {} FROM readings WHERE {}",
+                READING_COLUMNS, ZONE_FILTER
+            ),
+            ("default",),
+            super::parse_reading,
+        )?;
+        assert_eq!(
+            read.timestamp,
+            Some(Utc.with_ymd_and_hms(2026, 1, 10, 8, 30, 15).unwrap())
+        );
+        assert_eq!(read.temperature, Some(21.5));
+        legacy.execute(
+            "INSERT INTO events (kind, zone) VALUES ('test', 'north')",
+            (),
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn history() -> Result<(), Error> {
+        let connection = Connection::open_in_memory()?;
+        run_migrations(&connection)?;
+        let start = Utc.with_ymd_and_hms(2026, 3, 1, 10, 0, 0).unwrap();
+        for (minutes, temperature, zone) in [
+            (0, 20.0, "default"),
+            (20, 22.0, "default"),
+            (70, 25.0, "default"),
+            (80, 30.0, "north"),
+        ] {
+            connection.execute(
+                "INSERT INTO readings (time_ms, temperature, zone) VALUES (?1, ?2, ?3)",
+                (
+                    (start + TimeDelta::minutes(minutes)).timestamp_millis(),
+                    temperature,
+                    zone,
+                ),
+            )?;
+        }
+
+        let query = HistoryQuery {
+            from: start,
+            to: Some(start + TimeDelta::hours(3)),
+            bucket: None,
+            aggregate: Aggregate::Avg,
+        };
+        let rows = query_history(&connection, "default", &query, &Utc)?;
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].timestamp, Some(start));
+
+        let by_hour = HistoryQuery {
+            bucket: Some(Bucket::Hour),
+            ..query.clone()
+        };
+        let hourly = query_history(&connection, "default", &by_hour, &Utc)?;
+        assert_eq!(hourly.len(), 2);
+        assert_eq!(hourly[0].temperature, Some(21.0));
+        assert_eq!(hourly[1].timestamp, Some(start + TimeDelta::hours(1)));
+
+        //Hours start at half past in zones with a half hour offset
+        let india = FixedOffset::east_opt(5 * 3600 + 1800).unwrap();
+        let hourly = query_history(&connection, "default", &by_hour, &india)?;
+        assert_eq!(hourly.len(), 2);
+        assert_eq!(hourly[0].timestamp, Some(start - TimeDelta::minutes(30)));
+
+        let rows = query_history(&connection, "default", &query, &Utc)?;
+        let last = aggregate(rows, Bucket::Day, Aggregate::Last, &Utc);
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].temperature, Some(25.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn retention() -> Result<(), Error> {
+        let connection = Connection::open_in_memory()?;
+        run_migrations(&connection)?;
+        let now = Utc.with_ymd_and_hms(2026, 6, 1, 12, 0, 0).unwrap();
+        let stored = |days: i64, minutes: i64| {
+            (now - TimeDelta::days(days) + TimeDelta::minutes(minutes)).timestamp_millis()
+        };
+        for (time, temperature, zone) in [
+            (stored(40, 0), 20.0, "default"),
+            (stored(40, 10), 24.0, "default"),
+            (stored(40, 20), 30.0, "north"),
+            (stored(400, 0), 10.0, "north"),
+            (stored(1, 0), 22.0, "default"),
+        ] {
+            connection.execute(
+                "INSERT INTO readings (time_ms, temperature, zone) VALUES (?1, ?2, ?3)",
+                (time, temperature, zone),
+            )?;
+        }
+        let retention = Retention {
+            daily_days: Some(1000),
+            ..Default::default()
+        };
+        let expected = Pruned {
+            readings: 4,
+            hourly: 1,
+            daily: 0,
+        };
+
+        //Nothing changes in a dry run
+        assert_eq!(
+            prune_readings(&connection, &retention, now, true)?,
+            expected
+        );
+        let count = |table: &str| {
+            connection.query_one(&format!("SELECT COUNT(*) FROM {}", table), (), |row| {
+                row.get::<_, u32>(0)
+            })
+        };
+        assert_eq!(count("readings")?, 5);
+
+        assert_eq!(
+            prune_readings(&connection, &retention, now, false)?,
+            expected
+        );
+        assert_eq!(count("readings")?, 1);
+        assert_eq!(count("readings_daily")?, 1);
+        let average: f32 = connection.query_one(
+            "SELECT temperature FROM readings_hourly WHERE zone = 'default'",
+            (),
+            |row| row.get(0),
+        )?;
+        assert_eq!(average, 22.0);
+
+        //The pruned range is still there as averages
+        let history = |from: i64, to: i64| {
+            query_history(
+                &connection,
+                "default",
+                &HistoryQuery {
+                    from: now - TimeDelta::days(from),
+                    to: Some(now - TimeDelta::days(to)),
+                    bucket: None,
+                    aggregate: Aggregate::Avg,
+                },
+                &Utc,
+            )
+        };
+        let hourly = history(41, 39)?;
+        assert_eq!(hourly.len(), 1);
+        assert_eq!(hourly[0].temperature, Some(22.0));
+        assert_eq!(history(41, 0)?.len(), 2);
+        connection.execute(
+            "INSERT INTO readings (time_ms, temperature, zone) VALUES (?1, 12.0, 'north')",
+            (stored(401, 0),),
+        )?;
+        prune_readings(&connection, &retention, now, false)?;
+        let daily = query_history(
+            &connection,
+            "north",
+            &HistoryQuery {
+                from: now - TimeDelta::days(402),
+                to: Some(now - TimeDelta::days(399)),
+                bucket: None,
+                aggregate: Aggregate::Avg,
+            },
+            &Utc,
+        )?;
+        assert_eq!(daily.len(), 2);
+        assert_eq!(daily[1].temperature, Some(10.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_prune() -> Result<(), Error> {
+        let connection = Connection::open_in_memory()?;
+        run_migrations(&connection)?;
+        let now = Utc.with_ymd_and_hms(2026, 6, 1, 12, 0, 0).unwrap();
+        let retention = Retention::default();
+        let prune_with = |readings: &[(i64, i64, f32)]| -> Result<(), Error> {
+            for (days, minutes, temperature) in readings {
+                let time = now - TimeDelta::days(*days) + TimeDelta::minutes(*minutes);
+                connection.execute(
+                    "INSERT INTO readings (time_ms, temperature) VALUES (?1, ?2)",
+                    (time.timestamp_millis(), temperature),
+                )?;
+            }
+            prune_readings(&connection, &retention, now, false)?;
+            Ok(())
+        };
+        let bucket = |table: &str| {
+            connection.query_one(
+                &format!("SELECT temperature, samples FROM {}", table),
+                (),
+                |row| Ok((row.get::<_, f32>(0)?, row.get::<_, u32>(1)?)),
+            )
+        };
+
+        //The same hour and day are pruned twice, the second run adds to the first
+        prune_with(&[(40, 0, 20.0), (400, 0, 10.0)])?;
+        prune_with(&[(40, 10, 26.0), (40, 20, 32.0), (400, 30, 16.0)])?;
+        assert_eq!(bucket("readings_hourly")?, (26.0, 3));
+        assert_eq!(bucket("readings_daily")?, (13.0, 2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn zone_index() -> Result<(), Error> {
+        let connection = Connection::open_in_memory()?;
+        run_migrations(&connection)?;
+        let mut stmt = connection.prepare(&format!(
+            "EXPLAIN QUERY PLAN SELECT {} FROM readings WHERE {} AND time_ms >= ?2 ORDER BY time_ms DESC",
+            READING_COLUMNS, ZONE_FILTER
+        ))?;
+        let plan = stmt
+            .query_map(("default", 0), |row| row.get::<_, String>(3))?
+            .collect::<Result<Vec<String>, Error>>()?;
+        assert!(plan.iter().any(|step| step.contains("readings_zone_time")));
+
+        let plan = connection
+            .prepare(
+                "EXPLAIN QUERY PLAN SELECT kind FROM events WHERE zone = ?1 AND time_stamp >= ?2",
+            )?
+            .query_map(("default", "2026-01-01"), |row| row.get::<_, String>(3))?
+            .collect::<Result<Vec<String>, Error>>()?;
+        assert!(plan.iter().any(|step| step.contains("events_zone_time")));
+
+        Ok(())
+    }
 }
@@ -2,10 +2,15 @@
 extern crate rust_i18n;
 i18n!(fallback = "en");
 
+pub mod calibration;
 pub mod context;
 pub mod credentials;
 pub mod db_client;
 pub mod locales;
+pub mod protocol;
 pub mod rest_client;
+pub mod retention;
+pub mod schedule;
+pub mod serial;
 pub mod settings;
 pub mod state_handling;
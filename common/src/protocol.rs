@@ -0,0 +1,258 @@
+use crate::db_client::Reading;
+use crate::settings::IOFlags;
+use std::io;
+use std::io::ErrorKind::InvalidData;
+use std::io::Read;
+
+//Frame layout: START | LEN | TYPE | ID | PAYLOAD (LEN bytes) | CRC-8
+//The checksum covers every byte between START and CRC
+pub const START_BYTE: u8 = 0x7E;
+pub const PROTOCOL_VERSION: u8 = 1;
+
+//Sensor data payloads are sequences of a variable code followed by a little endian f32
+pub const TEMPERATURE: u8 = 0x01;
+pub const AIR_HUMIDITY: u8 = 0x02;
+pub const SOIL_HUMIDITY: u8 = 0x03;
+pub const LUMINOSITY: u8 = 0x04;
+pub const AIR_QUALITY: u8 = 0x05;
+pub const PH: u8 = 0x06;
+pub const EC: u8 = 0x07;
+pub const TANK_LEVEL: u8 = 0x08;
+pub const SOIL_TEMPERATURE: u8 = 0x09;
+pub const PRESSURE: u8 = 0x0A;
+pub const LEAF_WETNESS: u8 = 0x0B;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MessageType {
+    Command = 0x01,
+    SensorPoll = 0x02,
+    SensorData = 0x03,
+    Ack = 0x04,
+    Nack = 0x05,
+    Hello = 0x06,
+    HelloReply = 0x07,
+}
+
+//Answer to the handshake, flags are the values the firmware was compiled with
+#[derive(Debug, PartialEq)]
+pub struct FirmwareInfo {
+    pub version: String,
+    pub protocol_version: u8,
+    pub flags: IOFlags,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    pub kind: MessageType,
+    pub id: u8,
+    pub payload: Vec<u8>,
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = io::Error;
+    fn try_from(value: u8) -> Result<Self, io::Error> {
+        let res = match value {
+            0x01 => MessageType::Command,
+            0x02 => MessageType::SensorPoll,
+            0x03 => MessageType::SensorData,
+            0x04 => MessageType::Ack,
+            0x05 => MessageType::Nack,
+            0x06 => MessageType::Hello,
+            0x07 => MessageType::HelloReply,
+            _ => {
+                return Err(io::Error::new(
+                    InvalidData,
+                    t!("serial.protocol.unknown_type", kind = value),
+                ));
+            }
+        };
+        Ok(res)
+    }
+}
+
+//CRC-8 with polynomial 0x07, the same one computed by the microcontroller
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+impl Frame {
+    pub fn new(kind: MessageType, id: u8, payload: Vec<u8>) -> Self {
+        Frame { kind, id, payload }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, io::Error> {
+        let len = u8::try_from(self.payload.len()).or(Err(io::Error::new(
+            InvalidData,
+            t!("serial.protocol.too_long", length = self.payload.len()),
+        )))?;
+
+        let mut bytes = vec![START_BYTE, len, self.kind as u8, self.id];
+        bytes.extend_from_slice(&self.payload);
+        bytes.push(crc8(&bytes[1..]));
+
+        Ok(bytes)
+    }
+
+    //Discards anything before the start byte, so leftovers of partial frames are skipped
+    pub fn read_from(reader: &mut impl Read) -> Result<Frame, io::Error> {
+        let mut byte = [0u8; 1];
+        loop {
+            reader.read_exact(&mut byte)?;
+            if byte[0] == START_BYTE {
+                break;
+            }
+        }
+
+        let mut header = [0u8; 3];
+        reader.read_exact(&mut header)?;
+        let [len, kind, id] = header;
+
+        let mut payload = vec![0u8; len as usize];
+        reader.read_exact(&mut payload)?;
+        reader.read_exact(&mut byte)?;
+
+        let mut checked = header.to_vec();
+        checked.extend_from_slice(&payload);
+        if crc8(&checked) != byte[0] {
+            return Err(io::Error::new(InvalidData, t!("serial.protocol.crc")));
+        }
+
+        Ok(Frame {
+            kind: kind.try_into()?,
+            id,
+            payload,
+        })
+    }
+}
+
+//Each entry is an actuator channel followed by its output percentage (0 is off, 100 is on)
+pub fn command_payload(outputs: &[(u8, u8)]) -> Vec<u8> {
+    outputs
+        .iter()
+        .flat_map(|(channel, value)| [*channel, *value])
+        .collect()
+}
+
+pub fn parse_sensor_data(payload: &[u8]) -> Result<Reading, io::Error> {
+    if !payload.len().is_multiple_of(5) {
+        return Err(io::Error::new(InvalidData, t!("serial.invalid_data")));
+    }
+
+    let mut read = Reading::new();
+    for chunk in payload.chunks_exact(5) {
+        let value = Some(f32::from_le_bytes([chunk[1], chunk[2], chunk[3], chunk[4]]));
+        match chunk[0] {
+            TEMPERATURE => read.temperature = value,
+            AIR_HUMIDITY => read.air_humidity = value,
+            SOIL_HUMIDITY => read.soil_humidity = value,
+            LUMINOSITY => read.luminosity = value,
+            AIR_QUALITY => read.air_quality = value,
+            PH => read.ph = value,
+            EC => read.ec = value,
+            TANK_LEVEL => read.tank_level = value,
+            SOIL_TEMPERATURE => read.soil_temperature = value,
+            PRESSURE => read.pressure = value,
+            LEAF_WETNESS => read.leaf_wetness = value,
+            _ => return Err(io::Error::new(InvalidData, t!("serial.invalid_data"))),
+        }
+    }
+    Ok(read)
+}
+
+//Hello reply payload: MAJOR | MINOR | PATCH | PROTOCOL | SENSORS | ACTUATORS | INVERT
+//The three flags are little endian u32 so more devices can be added without a version bump
+pub fn parse_hello(payload: &[u8]) -> Result<FirmwareInfo, io::Error> {
+    if payload.len() < 16 {
+        return Err(io::Error::new(InvalidData, t!("serial.invalid_data")));
+    }
+    let flag =
+        |i: usize| u32::from_le_bytes([payload[i], payload[i + 1], payload[i + 2], payload[i + 3]]);
+
+    Ok(FirmwareInfo {
+        version: format!("{}.{}.{}", payload[0], payload[1], payload[2]),
+        protocol_version: payload[3],
+        flags: IOFlags {
+            sensors_flag: flag(4),
+            actuators_flag: flag(8),
+            inverted_flag: flag(12),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{
+        Frame, MessageType, SOIL_HUMIDITY, START_BYTE, TEMPERATURE, command_payload, parse_hello,
+        parse_sensor_data,
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        let frame = Frame::new(
+            MessageType::Command,
+            7,
+            command_payload(&[(3, 100), (4, 0)]),
+        );
+        let bytes = frame.encode().unwrap();
+
+        //Garbage from a previous partial frame must be skipped
+        let mut stream = vec![0x12, 0x00];
+        stream.extend(bytes);
+
+        assert_eq!(Frame::read_from(&mut Cursor::new(stream)).unwrap(), frame);
+    }
+
+    #[test]
+    fn corrupted_frame() {
+        let mut bytes = Frame::new(MessageType::Ack, 1, vec![]).encode().unwrap();
+        bytes[3] ^= 0xFF;
+
+        assert!(Frame::read_from(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let bytes = vec![START_BYTE, 4, MessageType::SensorData as u8, 2, 0];
+
+        assert!(Frame::read_from(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn sensor_data() {
+        let mut payload = vec![TEMPERATURE];
+        payload.extend(21.5f32.to_le_bytes());
+        payload.push(SOIL_HUMIDITY);
+        payload.extend(48.0f32.to_le_bytes());
+
+        let read = parse_sensor_data(&payload).unwrap();
+        assert_eq!(read.temperature, Some(21.5));
+        assert_eq!(read.soil_humidity, Some(48.0));
+        assert_eq!(read.luminosity, None);
+    }
+
+    #[test]
+    fn hello() {
+        let mut payload = vec![1, 2, 0, 1];
+        payload.extend(24u32.to_le_bytes());
+        payload.extend(12u32.to_le_bytes());
+        payload.extend(8u32.to_le_bytes());
+
+        let info = parse_hello(&payload).unwrap();
+        assert_eq!(info.version, "1.2.0");
+        assert_eq!(info.protocol_version, 1);
+        assert_eq!(info.flags.sensors_flag, 24);
+        assert_eq!(info.flags.inverted_flag, 8);
+    }
+}
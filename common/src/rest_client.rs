@@ -10,8 +10,8 @@ use std::io;
 use std::io::ErrorKind::HostUnreachable;
 
 #[derive(Deserialize)]
+#[allow(non_snake_case)]
 pub struct Output {
-    #[allow(non_snake_case)]
     pub statusCode: i32,
     pub message: String,
 }
@@ -62,12 +62,10 @@ pub async fn get_evaluation(
         var("REST_URL").unwrap_or("https://api.proyectocultiva.org".to_string())
     );
 
-    //json! macro includes None values as null, I converted it to HashMap first to remove them
-    let clean_act: HashMap<String, bool> = activation.into();
     let content = json!({
         "readings": readings,
         "context": context,
-        "activation": clean_act,
+        "activation": activation,
         "image": image
     });
 
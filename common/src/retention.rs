@@ -0,0 +1,162 @@
+use crate::db_client::{Pruned, prune};
+use crate::settings::{Retention, load_conf, zone_path};
+use std::error::Error;
+use std::fs::{read_dir, remove_file};
+use std::io;
+use std::io::ErrorKind::NotFound;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+pub const CAPTURES_DIR: &str = "/var/lib/cultiva/captures/";
+
+//Captures removed from a zone
+#[derive(Debug, Default, PartialEq)]
+pub struct Removed {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub readings: Pruned,
+    //By zone name
+    pub captures: Vec<(String, Removed)>,
+}
+
+//Remove the captures older than the policy allows, then the oldest ones while the directory is
+//over quota. The newest is always kept since it's served when the camera fails
+fn prune_captures(
+    dir: &str,
+    retention: &Retention,
+    now: SystemTime,
+    dry_run: bool,
+) -> Result<Removed, io::Error> {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == NotFound => return Ok(Removed::default()),
+        Err(e) => return Err(e),
+    };
+
+    let mut captures: Vec<(PathBuf, SystemTime, u64)> = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let meta = path.metadata()?;
+        if meta.is_file() && path.extension().is_some_and(|e| e == "jpg") {
+            captures.push((path, meta.modified()?, meta.len()));
+        }
+    }
+    captures.sort_by_key(|(_, modified, _)| *modified);
+    let mut total: u64 = captures.iter().map(|(_, _, size)| size).sum();
+    captures.pop();
+
+    let max_age = retention
+        .capture_days
+        .map(|days| Duration::from_hours(days as u64 * 24));
+    let quota = retention.capture_quota.map(|mb| mb * 1024 * 1024);
+    let mut removed = Removed::default();
+    for (path, modified, size) in captures {
+        let age = now.duration_since(modified).unwrap_or_default();
+        let too_old = max_age.is_some_and(|max| age > max);
+        let over_quota = quota.is_some_and(|quota| total > quota);
+        if !too_old && !over_quota {
+            continue;
+        }
+
+        if !dry_run {
+            remove_file(&path)?;
+        }
+        total -= size;
+        removed.files += 1;
+        removed.bytes += size;
+    }
+
+    Ok(removed)
+}
+
+// Public functions --------------------------------------------------------------------------------
+//Apply the retention policy of the settings to the readings and the captures of every zone
+pub fn run(dry_run: bool) -> Result<Report, Box<dyn Error>> {
+    let config = load_conf()?;
+    let mut report = Report {
+        readings: prune(&config.retention, dry_run)?,
+        captures: Vec::new(),
+    };
+    for zone in config.zones() {
+        let dir = zone_path(&zone.name, CAPTURES_DIR);
+        let removed = prune_captures(&dir, &config.retention, SystemTime::now(), dry_run)?;
+        report.captures.push((zone.name, removed));
+    }
+
+    Ok(report)
+}
+
+pub fn print_report(report: &Report) {
+    let readings = &report.readings;
+    println!(
+        "{}",
+        t!(
+            "retention.readings",
+            readings = readings.readings,
+            hourly = readings.hourly,
+            daily = readings.daily
+        )
+    );
+    for (zone, removed) in &report.captures {
+        println!(
+            "{}",
+            t!(
+                "retention.captures",
+                zone = zone,
+                files = removed.files,
+                size = format!("{:.1}", removed.bytes as f64 / (1024.0 * 1024.0))
+            )
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::retention::{Removed, prune_captures};
+    use crate::settings::Retention;
+    use std::fs::{File, create_dir_all, read_dir, remove_dir_all};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn captures() {
+        let dir = std::env::temp_dir().join("cultiva_retention_test/");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        for (name, days) in [("a", 100), ("b", 95), ("c", 10), ("d", 1)] {
+            let file = File::create(dir.join(format!("{}.jpg", name))).unwrap();
+            file.set_len(1024 * 1024).unwrap();
+            file.set_modified(now - Duration::from_hours(days * 24))
+                .unwrap();
+        }
+        let path = dir.to_str().unwrap();
+        let count = || read_dir(&dir).unwrap().count();
+
+        //Past the age limit
+        let retention = Retention::default();
+        let removed = prune_captures(path, &retention, now, true).unwrap();
+        assert_eq!(removed.files, 2);
+        assert_eq!(count(), 4);
+
+        //The quota also removes the oldest recent one, but never the newest
+        let retention = Retention {
+            capture_quota: Some(1),
+            ..Default::default()
+        };
+        let removed = prune_captures(path, &retention, now, false).unwrap();
+        assert_eq!(
+            removed,
+            Removed {
+                files: 3,
+                bytes: 3 * 1024 * 1024
+            }
+        );
+        assert_eq!(count(), 1);
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use crate::settings::{write_zone_file, zone_path};
+use crate::state_handling::ActivationState;
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+
+//Precedence, from highest to lowest: actuators in manual mode are never touched, then On windows,
+//then scheduled actuators are kept off outside of their windows, and finally the auto controllers
+//inside Allow windows
+#[derive(Deserialize, Serialize, Default, Clone, Debug)]
+pub struct Schedule {
+    #[serde(default)]
+    pub windows: Vec<ScheduleWindow>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ScheduleWindow {
+    pub actuator: String,
+    //Times as HH:MM or HH:MM:SS, windows ending before they start cross midnight
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    #[serde(default)]
+    pub mode: WindowMode,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy, Debug, PartialEq)]
+pub enum WindowMode {
+    //Forces the actuator on, like an irrigation pulse, and off once it closes
+    #[default]
+    On,
+    //Lets the auto controller run only inside the window, like a photoperiod for lighting
+    Allow,
+}
+
+impl ScheduleWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl Schedule {
+    //State forced by the schedule on actuators in auto mode, None leaves them to the controllers
+    pub fn decide(&self, auto_modes: &ActivationState, time: NaiveTime) -> ActivationState {
+        let mut decision = ActivationState::new();
+        for w in &self.windows {
+            let name = &w.actuator;
+            if !auto_modes.get(name).is_some_and(|x| x) || decision.get(name).is_some() {
+                continue;
+            }
+
+            let windows = || self.windows.iter().filter(|w| &w.actuator == name);
+            let in_mode = |mode| windows().any(|w| w.mode == mode && w.contains(time));
+
+            if in_mode(WindowMode::On) {
+                decision.set(name, Some(true));
+            } else if !in_mode(WindowMode::Allow) {
+                decision.set(name, Some(false));
+            }
+        }
+
+        decision
+    }
+}
+
+const SCHEDULE_FILE: &str = "/etc/cultiva/schedule.toml";
+
+pub fn set_schedule(zone: &str, schedule: Schedule) -> Result<(), Box<dyn Error>> {
+    let content = toml::to_string(&schedule)?;
+    write_zone_file(zone, SCHEDULE_FILE, content)?;
+
+    Ok(())
+}
+
+//A missing file means there's nothing scheduled
+pub fn get_schedule(zone: &str) -> Result<Schedule, Box<dyn Error>> {
+    let path = zone_path(zone, SCHEDULE_FILE);
+    if !fs::exists(&path)? {
+        return Ok(Schedule::default());
+    }
+
+    Ok(toml::from_str(&fs::read_to_string(&path)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schedule::{Schedule, ScheduleWindow, WindowMode};
+    use crate::state_handling::ActivationState;
+    use chrono::NaiveTime;
+
+    fn window(actuator: &str, start: &str, end: &str, mode: WindowMode) -> ScheduleWindow {
+        ScheduleWindow {
+            actuator: actuator.to_string(),
+            start: start.parse().unwrap(),
+            end: end.parse().unwrap(),
+            mode,
+        }
+    }
+
+    fn at(time: &str) -> NaiveTime {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn windows() {
+        let schedule = Schedule {
+            windows: vec![
+                window("lighting", "06:00", "22:00", WindowMode::Allow),
+                window("irrigator", "07:00", "07:01:30", WindowMode::On),
+                window("uv", "23:00", "01:00", WindowMode::On),
+            ],
+        };
+        let auto = ActivationState::from([("irrigator", true), ("lighting", true), ("uv", true)]);
+
+        let morning = schedule.decide(&auto, at("07:01"));
+        assert_eq!(morning.get("irrigator"), Some(true));
+        assert_eq!(morning.get("lighting"), None);
+        assert_eq!(morning.get("uv"), Some(false));
+
+        let night = schedule.decide(&auto, at("00:30"));
+        assert_eq!(night.get("irrigator"), Some(false));
+        assert_eq!(night.get("lighting"), Some(false));
+        assert_eq!(night.get("uv"), Some(true));
+    }
+
+    #[test]
+    fn off_after_window() {
+        let schedule = Schedule {
+            windows: vec![
+                window("irrigator", "07:00", "07:01:30", WindowMode::On),
+                window("fan", "12:00", "13:00", WindowMode::On),
+                window("fan", "08:00", "20:00", WindowMode::Allow),
+            ],
+        };
+        let auto = ActivationState::from([("irrigator", true), ("fan", true)]);
+
+        assert_eq!(
+            schedule.decide(&auto, at("07:00")).get("irrigator"),
+            Some(true)
+        );
+        assert_eq!(
+            schedule.decide(&auto, at("07:01:30")).get("irrigator"),
+            Some(false)
+        );
+        //Past the On window but still in the Allow one, the controller takes over again
+        assert_eq!(schedule.decide(&auto, at("12:30")).get("fan"), Some(true));
+        assert_eq!(schedule.decide(&auto, at("14:00")).get("fan"), None);
+        assert_eq!(schedule.decide(&auto, at("21:00")).get("fan"), Some(false));
+    }
+
+    #[test]
+    fn manual_mode_wins() {
+        let schedule = Schedule {
+            windows: vec![window("irrigator", "07:00", "08:00", WindowMode::On)],
+        };
+        let manual = ActivationState::from([("irrigator", false)]);
+
+        assert_eq!(schedule.decide(&manual, at("07:30")).get("irrigator"), None);
+    }
+}
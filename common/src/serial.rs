@@ -0,0 +1,389 @@
+use crate::db_client::Reading;
+use crate::protocol::{
+    FirmwareInfo, Frame, MessageType, PROTOCOL_VERSION, command_payload, parse_hello,
+    parse_sensor_data,
+};
+use crate::settings::{Board, Protocol, Sensors};
+use serialport::{SerialPort, SerialPortInfo, SerialPortType};
+use std::error::Error;
+use std::fs::exists;
+use std::io::ErrorKind::{InvalidData, NotConnected, TimedOut, Unsupported};
+use std::io::{Read, Write};
+use std::thread::sleep;
+use std::time::Duration;
+
+//Single base-33 character, older firmware only understands this encoding
+fn encode_legacy(outputs: &[(u8, u8)]) -> Result<String, std::io::Error> {
+    let over_limit = |number: u32| {
+        std::io::Error::new(
+            Unsupported,
+            t!("error.over_limit", limit = 32, number = number),
+        )
+    };
+
+    let mut sum: u32 = 1;
+    for (channel, value) in outputs {
+        if *value > 0 {
+            sum = 1u32
+                .checked_shl(*channel as u32)
+                .and_then(|bit| sum.checked_add(bit))
+                .ok_or_else(|| over_limit(*channel as u32))?;
+        }
+    }
+
+    match char::from_digit(sum, 33) {
+        Some(c) => Ok(c.to_string().to_uppercase()),
+        None => Err(over_limit(sum)),
+    }
+}
+
+//USB attributes used to find the board again when it's plugged into a different port
+struct UsbIdentity {
+    vid: u16,
+    pid: u16,
+    serial_number: Option<String>,
+}
+
+//Connection to the microcontroller, shared by the service and the calibration wizard
+pub struct SerialLink {
+    port: Option<Box<dyn SerialPort>>,
+    path: String,
+    identity: Option<UsbIdentity>,
+    protocol: Protocol,
+    //In the order the legacy firmware reports them
+    sensors: Vec<Sensors>,
+    next_id: u8,
+}
+
+impl UsbIdentity {
+    fn matches(&self, info: &SerialPortInfo) -> bool {
+        let SerialPortType::UsbPort(usb) = &info.port_type else {
+            return false;
+        };
+        match &self.serial_number {
+            Some(serial) => usb.serial_number.as_ref() == Some(serial),
+            None => usb.vid == self.vid && usb.pid == self.pid,
+        }
+    }
+}
+
+impl SerialLink {
+    //A failed open isn't fatal, the connection supervisor keeps retrying
+    pub fn new(board: &Board, sensors: Vec<Sensors>) -> Self {
+        let identity = match (board.vid, board.pid) {
+            (Some(vid), Some(pid)) => Some(UsbIdentity {
+                vid,
+                pid,
+                serial_number: board.serial_number.clone(),
+            }),
+            _ => None,
+        };
+
+        let mut backend = SerialLink {
+            port: None,
+            path: board.port.clone(),
+            identity,
+            protocol: board.protocol,
+            sensors,
+            next_id: 0,
+        };
+        if let Err(e) = backend.open(&board.port) {
+            eprintln!("{}", t!("serial.init_error", error = e));
+        }
+
+        backend
+    }
+
+    fn open(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        let port = serialport::new(path, 9600)
+            .timeout(Duration::from_secs(5))
+            .open()?;
+
+        //Remember who answered on this path, unless settings already specify it
+        if self.identity.is_none()
+            && let Some(SerialPortType::UsbPort(usb)) = serialport::available_ports()?
+                .into_iter()
+                .find(|p| p.port_name == path)
+                .map(|p| p.port_type)
+        {
+            self.identity = Some(UsbIdentity {
+                vid: usb.vid,
+                pid: usb.pid,
+                serial_number: usb.serial_number,
+            });
+        }
+
+        self.path = path.to_string();
+        self.port = Some(port);
+        Ok(())
+    }
+
+    fn port(&mut self) -> Result<&mut Box<dyn SerialPort>, std::io::Error> {
+        self.port
+            .as_mut()
+            .ok_or(std::io::Error::new(NotConnected, t!("serial.unavailable")))
+    }
+
+    //Timeouts and bad data are expected from time to time, any other I/O error means the port is gone
+    pub fn check_connection<T>(
+        &mut self,
+        res: Result<T, Box<dyn Error>>,
+    ) -> Result<T, Box<dyn Error>> {
+        if let Err(e) = &res
+            && let Some(io_err) = e.downcast_ref::<std::io::Error>()
+            && !matches!(io_err.kind(), TimedOut | InvalidData | NotConnected)
+            && self.port.is_some()
+        {
+            eprintln!("{}", t!("serial.lost", error = e));
+            self.port = None;
+        }
+        res
+    }
+
+    //Send a frame and wait for the response carrying the same id
+    fn transact(&mut self, kind: MessageType, payload: Vec<u8>) -> Result<Frame, Box<dyn Error>> {
+        self.next_id = self.next_id.wrapping_add(1);
+        let request = Frame::new(kind, self.next_id, payload);
+        let port = self.port()?;
+        port.write_all(&request.encode()?)?;
+        port.flush()?;
+
+        //Frames with other ids are leftovers from requests that already timed out
+        for _ in 0..8 {
+            let response = Frame::read_from(port)?;
+            if response.id == request.id {
+                return Ok(response);
+            }
+            eprintln!("{}", t!("serial.protocol.stale", id = response.id));
+        }
+
+        Err(Box::new(std::io::Error::new(
+            InvalidData,
+            t!("serial.protocol.no_response"),
+        )))
+    }
+
+    fn send_framed(&mut self, outputs: &[(u8, u8)]) -> Result<(), Box<dyn Error>> {
+        let payload = command_payload(outputs);
+        let encoded = format!("{:?}", payload);
+
+        let response = self.transact(MessageType::Command, payload)?;
+        match response.kind {
+            MessageType::Ack => {
+                println!("{}", t!("serial.command.sent", command = encoded));
+                Ok(())
+            }
+            MessageType::Nack => Err(Box::new(std::io::Error::new(
+                InvalidData,
+                t!(
+                    "serial.protocol.nack",
+                    code = format!("{:?}", response.payload)
+                ),
+            ))),
+            kind => Err(Box::new(std::io::Error::new(
+                InvalidData,
+                t!("serial.protocol.unexpected", kind = format!("{:?}", kind)),
+            ))),
+        }
+    }
+
+    fn send_legacy(&mut self, outputs: &[(u8, u8)]) -> Result<(), Box<dyn Error>> {
+        let encoded = encode_legacy(outputs)?;
+        let port = self.port()?;
+        let res = port.write_all(encoded.as_bytes());
+        match res {
+            Ok(_) => {
+                println!("{}", t!("serial.command.sent", command = encoded));
+            }
+            Err(e) => {
+                println!("{}", t!("serial.command.error", error = e));
+                return Err(e.into());
+            }
+        }
+
+        //Confirm command was received
+        let mut buffer: Vec<u8> = vec![0; 1];
+        match port.read_exact(buffer.as_mut_slice()) {
+            Ok(_) => {
+                let response = String::from_utf8(buffer);
+                match response {
+                    Err(e) => {
+                        eprintln!("{}", t!("serial.command.unchecked", error = e));
+                    }
+                    Ok(value) => {
+                        if value != encoded {
+                            eprintln!(
+                                "{}",
+                                t!("serial.command.unmatched", sent = encoded, received = value)
+                            );
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", t!("serial.command.unchecked", error = e));
+            }
+        }
+        port.flush()?;
+
+        Ok(())
+    }
+
+    pub fn request_info(&mut self) -> Result<Option<FirmwareInfo>, Box<dyn Error>> {
+        if self.protocol == Protocol::Legacy {
+            return Ok(None);
+        }
+
+        let response = self.transact(MessageType::Hello, vec![PROTOCOL_VERSION])?;
+        if response.kind != MessageType::HelloReply {
+            return Err(Box::new(std::io::Error::new(
+                InvalidData,
+                t!(
+                    "serial.protocol.unexpected",
+                    kind = format!("{:?}", response.kind)
+                ),
+            )));
+        }
+        Ok(Some(parse_hello(&response.payload)?))
+    }
+
+    pub fn request_outputs(&mut self, outputs: &[(u8, u8)]) -> Result<(), Box<dyn Error>> {
+        match self.protocol {
+            Protocol::Legacy => self.send_legacy(outputs),
+            Protocol::Framed => self.send_framed(outputs),
+        }
+    }
+
+    //Request sensor data and parse it as a reading
+    pub fn request_reading(&mut self) -> Result<Reading, Box<dyn Error>> {
+        if self.protocol == Protocol::Framed {
+            let response = self.transact(MessageType::SensorPoll, vec![])?;
+            if response.kind != MessageType::SensorData {
+                return Err(Box::new(std::io::Error::new(
+                    InvalidData,
+                    t!(
+                        "serial.protocol.unexpected",
+                        kind = format!("{:?}", response.kind)
+                    ),
+                )));
+            }
+            return Ok(parse_sensor_data(&response.payload)?);
+        }
+
+        let port = self.port()?;
+        port.write_all("0".as_bytes())?;
+
+        let mut serial_buf: Vec<u8> = vec![0; 64];
+        //Arduino is quite slow, so it's best to give some margin for a response
+        sleep(Duration::from_millis(100));
+        let received = port.read(serial_buf.as_mut_slice())?;
+        serial_buf.truncate(received);
+        port.flush()?;
+
+        let message = String::from_utf8(serial_buf)?;
+        parse_legacy_reading(&message, &self.sensors)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        //The device file disappears as soon as the cable is pulled
+        self.port.is_some() && exists(&self.path).unwrap_or(false)
+    }
+
+    pub fn disconnect(&mut self) {
+        self.port = None;
+    }
+
+    pub fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.port = None;
+
+        //Match the board by its USB identity, the path may change after replugging it
+        let path = match &self.identity {
+            Some(identity) => serialport::available_ports()?
+                .into_iter()
+                .find(|p| identity.matches(p))
+                .map(|p| p.port_name)
+                .unwrap_or(self.path.clone()),
+            None => self.path.clone(),
+        };
+
+        self.open(&path)?;
+        println!("{}", t!("serial.reconnected", port = path));
+
+        Ok(())
+    }
+}
+
+//Comma separated values, one per sensor in the order they appear in the settings (two for the DHT11)
+pub fn parse_legacy_reading(message: &str, sensors: &[Sensors]) -> Result<Reading, Box<dyn Error>> {
+    let mut data = message.split(",").collect::<Vec<&str>>();
+    data.pop();
+
+    //Check if input values correspond to the sensors specification
+    let expect_len = sensors.len()
+        + if sensors.contains(&Sensors::DHT11) {
+            1
+        } else {
+            0
+        };
+    if expect_len != data.len() {
+        return Err(Box::from(std::io::Error::new(
+            InvalidData,
+            t!("serial.invalid_data"),
+        )));
+    }
+
+    //Consume all values in data while iterating, NOTE: This only works if the configuration sensors
+    //appear in the same order as the output value
+    let mut read = Reading::new();
+    for s in sensors {
+        if *s == Sensors::DHT11 {
+            read.temperature = Some(data[0].trim().parse::<f32>()?);
+            read.air_humidity = Some(data[1].trim().parse::<f32>()?);
+            data.drain(0..2);
+            continue;
+        }
+
+        let value = Some(data.remove(0).trim().parse::<f32>()?);
+        match s {
+            Sensors::Thermometer => read.temperature = value,
+            Sensors::Hygrometer => read.air_humidity = value,
+            Sensors::SoilHygrometer => read.soil_humidity = value,
+            Sensors::Luminometer => read.luminosity = value,
+            Sensors::Co2 => read.air_quality = value,
+            Sensors::PH => read.ph = value,
+            Sensors::Ec => read.ec = value,
+            Sensors::TankLevel => read.tank_level = value,
+            Sensors::SoilThermometer => read.soil_temperature = value,
+            Sensors::Barometer => read.pressure = value,
+            Sensors::LeafWetness => read.leaf_wetness = value,
+            Sensors::DHT11 => {}
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serial::{encode_legacy, parse_legacy_reading};
+    use crate::settings::Sensors;
+
+    #[test]
+    fn legacy_reading() {
+        let sensors = [Sensors::Thermometer, Sensors::Hygrometer, Sensors::PH];
+        let read = parse_legacy_reading("21.5,60,6.8,", &sensors).unwrap();
+
+        assert_eq!(read.temperature, Some(21.5));
+        assert_eq!(read.air_humidity, Some(60.0));
+        assert_eq!(read.ph, Some(6.8));
+        assert!(parse_legacy_reading("21.5,", &sensors).is_err());
+    }
+
+    #[test]
+    fn legacy_outputs() {
+        assert_eq!(encode_legacy(&[(0, 100), (2, 0), (3, 40)]).unwrap(), "A");
+        //Channels the encoding can't hold are refused instead of overflowing
+        assert!(encode_legacy(&[(5, 100)]).is_err());
+        assert!(encode_legacy(&[(40, 100)]).is_err());
+        assert!(encode_legacy(&[(40, 0)]).is_ok());
+    }
+}
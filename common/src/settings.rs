@@ -1,39 +1,532 @@
+use crate::calibration::Calibration;
 use config::{Config, File};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::io::ErrorKind::NotFound;
 use std::io::{Error, ErrorKind};
 
 #[derive(Deserialize, Serialize, Default)]
 pub struct Settings {
     pub network: NetConf,
+    //Board, interface and control of the legacy zone, used when no zones are listed
+    #[serde(default)]
     pub physical_interface: IO,
+    #[serde(default)]
     pub board: Board,
+    //Keyed by actuator id
+    #[serde(default)]
+    pub control: HashMap<String, ActuatorControl>,
+    #[serde(default)]
+    pub zones: Vec<Zone>,
+    #[serde(default)]
+    pub failsafe: Failsafe,
+    #[serde(default)]
+    pub persistence: Persistence,
+    //Keyed by variable name (temperature, soil_humidity...), applied before anything else sees
+    //the readings
+    #[serde(default)]
+    pub calibration: HashMap<String, Calibration>,
+    #[serde(default)]
+    pub sampling: Sampling,
+    #[serde(default)]
+    pub intervals: Intervals,
+    #[serde(default)]
+    pub retention: Retention,
 }
+
+//Name of the zone made from settings written before zones existed
+pub const DEFAULT_ZONE: &str = "default";
+
+//Part of the greenhouse run by its own board, with its own ranges, context and camera
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct Zone {
+    pub name: String,
+    pub board: Board,
+    pub physical_interface: IO,
+    //Keyed by actuator id
+    #[serde(default)]
+    pub control: HashMap<String, ActuatorControl>,
+    //Index of the camera pointed at the zone
+    #[serde(default)]
+    pub camera: u32,
+    //Profiles of the sensors of this board, on top of the ones shared by every zone
+    #[serde(default)]
+    pub calibration: HashMap<String, Calibration>,
+    //The ones shared by every zone are used when missing
+    #[serde(default)]
+    pub sampling: Option<Sampling>,
+    #[serde(default)]
+    pub failsafe: Option<Failsafe>,
+}
+
+impl Settings {
+    //Listed zones, or the single legacy one
+    pub fn zones(&self) -> Vec<Zone> {
+        if !self.zones.is_empty() {
+            return self.zones.clone();
+        }
+        vec![Zone {
+            name: DEFAULT_ZONE.to_string(),
+            board: self.board.clone(),
+            physical_interface: self.physical_interface.clone(),
+            control: self.control.clone(),
+            ..Default::default()
+        }]
+    }
+
+    //Shared profiles replaced by the ones of the zone
+    pub fn calibration(&self, zone: &Zone) -> HashMap<String, Calibration> {
+        let mut profiles = self.calibration.clone();
+        profiles.extend(zone.calibration.clone());
+        profiles
+    }
+
+    pub fn sampling(&self, zone: &Zone) -> Sampling {
+        zone.sampling.clone().unwrap_or(self.sampling.clone())
+    }
+
+    pub fn failsafe(&self, zone: &Zone) -> Failsafe {
+        zone.failsafe.clone().unwrap_or(self.failsafe.clone())
+    }
+
+    //Zone with the given name, the first one when none is given
+    //Refuse what the boards can't do, so it isn't silently ignored later
+    pub fn validate(&self) -> Result<(), Error> {
+        for zone in self.zones() {
+            for a in &zone.physical_interface.actuators {
+                if a.channel as u32 >= u32::BITS {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        t!(
+                            "config.channel",
+                            actuator = a.id,
+                            zone = zone.name,
+                            channel = a.channel,
+                            max = u32::BITS - 1
+                        ),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn zone(&self, name: Option<&str>) -> Result<Zone, Error> {
+        let zones = self.zones();
+        let found = match name {
+            Some(name) => zones.into_iter().find(|z| z.name == name),
+            None => zones.into_iter().next(),
+        };
+        found.ok_or(Error::new(
+            NotFound,
+            t!("zones.unknown", zone = name.unwrap_or_default()),
+        ))
+    }
+}
+
+//Files of the legacy zone stay where they always were, the others get their own directory next
+//to them, like /var/lib/cultiva/zones/north/state.json
+pub fn zone_path(zone: &str, path: &str) -> String {
+    if zone == DEFAULT_ZONE {
+        return path.to_string();
+    }
+    let trimmed = path.trim_end_matches('/');
+    match trimmed.rsplit_once('/') {
+        Some((dir, name)) => format!("{}/zones/{}/{}{}", dir, zone, name, &path[trimmed.len()..]),
+        None => path.to_string(),
+    }
+}
+
+//Write a file of the zone, its directory is created the first time
+pub fn write_zone_file(zone: &str, path: &str, content: impl AsRef<[u8]>) -> Result<(), Error> {
+    let path = zone_path(zone, path);
+    if let Some(dir) = std::path::Path::new(&path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, content)
+}
+
 #[derive(Deserialize, Serialize, Default)]
 pub struct NetConf {
     pub online: bool,
 }
 
-#[derive(Deserialize, Serialize, Default)]
+#[derive(Deserialize, Serialize, Default, Clone)]
+#[serde(from = "LegacyIO")]
 pub struct IO {
     pub sensors: Vec<Sensors>,
-    pub actuators: Vec<Actuators>,
-    pub inverted: Vec<Actuators>,
+    pub actuators: Vec<ActuatorSpec>,
+}
+
+//Settings written before actuators were data listed them by kind, with the inverted ones apart
+#[derive(Deserialize)]
+struct LegacyIO {
+    sensors: Vec<Sensors>,
+    actuators: Vec<ActuatorEntry>,
+    #[serde(default)]
+    inverted: Vec<Actuators>,
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ActuatorEntry {
+    Kind(Actuators),
+    Spec(ActuatorSpec),
+}
+
+//A single actuator wired to the board, the id is the key used everywhere else (state, control
+//settings, schedules and commands)
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ActuatorSpec {
+    pub id: String,
+    pub kind: Actuators,
+    pub channel: u8,
+    #[serde(default)]
+    pub inverted: bool,
+    #[serde(default)]
+    pub output: OutputType,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy, Debug, PartialEq)]
+pub enum OutputType {
+    //Relay, only on or off
+    #[default]
+    Binary,
+    //PWM or dimmer, takes any percentage
+    Variable,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub struct IOFlags {
-    pub sensors_flag: u8,
-    pub actuators_flag: u8,
-    pub inverted_flag: u8,
+    pub sensors_flag: u32,
+    pub actuators_flag: u32,
+    pub inverted_flag: u32,
+}
+
+//Automatic control of an actuator, times are in seconds
+#[derive(Deserialize, Serialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct ActuatorControl {
+    //Margin a reading must go past the range limits before the automatic mode reacts
+    pub hysteresis: f32,
+    pub min_on: u64,
+    pub min_off: u64,
+    pub max_runtime: Option<u64>,
+    //Time kept off after reaching the maximum runtime, as long as the runtime itself when missing.
+    //Never shorter than min_off
+    pub lockout: Option<u64>,
+    //Reading followed by the controller, when missing the actuator's usual variable is used
+    pub variable: Option<Variable>,
+    pub controller: ControllerConfig,
+}
+
+//Reverse is for actuators that lower the variable, like shading does with luminosity
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub enum ControllerConfig {
+    //On below the minimum and off above the maximum, limits default to the supervision ranges
+    BangBang {
+        #[serde(default)]
+        min: Option<f32>,
+        #[serde(default)]
+        max: Option<f32>,
+        #[serde(default)]
+        reverse: bool,
+    },
+    Pid {
+        setpoint: f32,
+        kp: f32,
+        #[serde(default)]
+        ki: f32,
+        #[serde(default)]
+        kd: f32,
+        #[serde(default)]
+        reverse: bool,
+    },
+    //PID output turned into the fraction of each period the actuator stays on
+    TimeProportional {
+        setpoint: f32,
+        kp: f32,
+        #[serde(default)]
+        ki: f32,
+        #[serde(default)]
+        kd: f32,
+        #[serde(default)]
+        reverse: bool,
+        period: u64,
+    },
+}
+
+//Seconds between the steps of the reading loop, read again every cycle so they can be changed
+//without restarting the service
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Intervals {
+    //Before the first reading, the board may still be initializing the connection
+    pub startup: u64,
+    //Between readings used to evaluate the auto modes
+    pub control: u64,
+    //Between readings stored in the database, a coarser history than the control loop
+    pub storage: u64,
+    //Before trying again after a failed reading
+    pub retry: u64,
+}
+
+impl Default for Intervals {
+    fn default() -> Self {
+        Intervals {
+            startup: 5,
+            control: 10,
+            storage: 1800,
+            retry: 10,
+        }
+    }
+}
+
+//Several polls are combined into each reading, so a single glitch can't switch an actuator or get
+//stored as a real value
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct Sampling {
+    //Polls per reading
+    pub samples: u32,
+    //Milliseconds between polls
+    pub spacing: u64,
+    pub filter: Filter,
+    //Keyed by variable name, values outside are rejected, replacing the physical limits of the
+    //variable (uncalibrated sensors may report in other units)
+    pub bounds: HashMap<String, Bounds>,
+    //Keyed by variable name, largest change per second believed to be real
+    pub max_rate: HashMap<String, f32>,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling {
+            samples: 3,
+            spacing: 200,
+            filter: Default::default(),
+            bounds: HashMap::new(),
+            max_rate: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Debug, PartialEq)]
+#[serde(tag = "type")]
+pub enum Filter {
+    #[default]
+    Median,
+    //Mean without the given fraction of the lowest and the highest samples
+    TrimmedMean {
+        trim: f32,
+    },
+    //Mean of the samples smoothed with the previous readings, higher alpha follows them faster
+    Ema {
+        alpha: f32,
+    },
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: f32,
+    pub max: f32,
+}
+
+//How long the data is kept, older readings are rolled into hourly and then daily averages
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct Retention {
+    //Days of readings kept as they were stored
+    pub raw_days: u32,
+    //Days of hourly averages kept before rolling them into daily ones
+    pub hourly_days: u32,
+    //Daily averages are kept forever when missing
+    pub daily_days: Option<u32>,
+    //Captures older than this are removed
+    pub capture_days: Option<u32>,
+    //Megabytes the captures of each zone may take, the oldest are removed first
+    pub capture_quota: Option<u64>,
+    //Cron expression of the cleanup job, in local time
+    pub schedule: String,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            raw_days: 30,
+            hourly_days: 365,
+            daily_days: None,
+            capture_days: Some(90),
+            capture_quota: None,
+            schedule: "0 30 3 * * *".to_string(),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(default)]
+pub struct Persistence {
+    pub restore: RestorePolicy,
+}
+
+//What the actuators do when the daemon starts
+#[derive(Deserialize, Serialize, Default, Debug, Copy, Clone, PartialEq)]
+pub enum RestorePolicy {
+    //Active states, auto modes and pending pulses saved before stopping
+    #[default]
+    Last,
+    //Everything off and in automatic mode
+    SafeDefaults,
+}
+
+//Safe states forced when the readings can't be trusted, overriding every other kind of control
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct Failsafe {
+    //Consecutive failed polls before tripping
+    pub max_failed_polls: u32,
+    //Seconds without a successful reading before tripping
+    pub max_reading_age: Option<u64>,
+    //Keyed by actuator id like the control settings
+    pub safe_state: HashMap<String, bool>,
+    pub limits: Vec<HardLimit>,
+}
+
+//Absolute limit that forces an actuator to a state, no matter the ranges or the mode, like the
+//heater off above 40 °C or the irrigator off while the tank level is below 10%
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct HardLimit {
+    pub actuator: String,
+    pub variable: Variable,
+    #[serde(default)]
+    pub min: Option<f32>,
+    #[serde(default)]
+    pub max: Option<f32>,
+    pub state: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Variable {
+    Temperature,
+    AirHumidity,
+    SoilHumidity,
+    Luminosity,
+    AirQuality,
+    Ph,
+    Ec,
+    TankLevel,
+    SoilTemperature,
+    Pressure,
+    LeafWetness,
+}
+
+impl Variable {
+    pub const ALL: [Variable; 11] = [
+        Variable::Temperature,
+        Variable::AirHumidity,
+        Variable::SoilHumidity,
+        Variable::Luminosity,
+        Variable::AirQuality,
+        Variable::Ph,
+        Variable::Ec,
+        Variable::TankLevel,
+        Variable::SoilTemperature,
+        Variable::Pressure,
+        Variable::LeafWetness,
+    ];
+
+    //Values no working sensor can report
+    pub fn physical_bounds(&self) -> Bounds {
+        let (min, max) = match self {
+            Variable::Temperature | Variable::SoilTemperature => (-40.0, 85.0),
+            Variable::AirHumidity
+            | Variable::SoilHumidity
+            | Variable::TankLevel
+            | Variable::LeafWetness => (0.0, 100.0),
+            Variable::Luminosity => (0.0, 200000.0),
+            Variable::AirQuality => (0.0, 10000.0),
+            Variable::Ph => (0.0, 14.0),
+            Variable::Ec => (0.0, 20.0),
+            Variable::Pressure => (300.0, 1100.0),
+        };
+        Bounds { min, max }
+    }
+
+    //Read by analog probes, which report raw counts until they're calibrated
+    pub fn analog(&self) -> bool {
+        matches!(
+            self,
+            Variable::SoilHumidity
+                | Variable::Luminosity
+                | Variable::AirQuality
+                | Variable::Ph
+                | Variable::Ec
+                | Variable::TankLevel
+                | Variable::LeafWetness
+        )
+    }
+
+    //Same as the reading fields
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variable::Temperature => "temperature",
+            Variable::AirHumidity => "air_humidity",
+            Variable::SoilHumidity => "soil_humidity",
+            Variable::Luminosity => "luminosity",
+            Variable::AirQuality => "air_quality",
+            Variable::Ph => "ph",
+            Variable::Ec => "ec",
+            Variable::TankLevel => "tank_level",
+            Variable::SoilTemperature => "soil_temperature",
+            Variable::Pressure => "pressure",
+            Variable::LeafWetness => "leaf_wetness",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
 pub struct Board {
     pub name: String,
     pub port: String,
+    #[serde(default)]
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub on_mismatch: MismatchPolicy,
+    #[serde(default)]
+    pub backend: Backend,
+    //USB identity of the board, used to find it again if it's replugged into another port
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+}
+
+//Simulated runs a greenhouse model instead of talking to a microcontroller, for development
+#[derive(Deserialize, Serialize, Default, Debug, Copy, Clone, PartialEq)]
+pub enum Backend {
+    #[default]
+    Serial,
+    Simulated,
+}
+
+//Legacy is the single character bitmask understood by older firmware
+#[derive(Deserialize, Serialize, Default, Debug, Copy, Clone, PartialEq)]
+pub enum Protocol {
+    #[default]
+    Legacy,
+    Framed,
+}
+
+//What to do when the firmware doesn't match the configured sensors and actuators
+#[derive(Deserialize, Serialize, Default, Debug, Copy, Clone, PartialEq)]
+pub enum MismatchPolicy {
+    #[default]
+    Warn,
+    Refuse,
 }
 
-#[derive(Deserialize, Serialize, PartialEq)]
+#[derive(Deserialize, Serialize, PartialEq, Clone)]
 pub enum Sensors {
     DHT11,
     Thermometer,
@@ -42,15 +535,29 @@ pub enum Sensors {
     Luminometer,
     Co2,
     PH,
+    //Electrical conductivity of the nutrient solution (EC/TDS probe)
+    Ec,
+    TankLevel,
+    SoilThermometer,
+    //BME280 or similar
+    Barometer,
+    LeafWetness,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
 pub enum Actuators {
     Irrigator,
     Heater,
     Lighting,
     UV,
     Shading,
+    Fan,
+    Humidifier,
+    Dehumidifier,
+    Co2Injector,
+    NutrientPump,
+    PhUpPump,
+    PhDownPump,
 }
 
 impl Settings {
@@ -59,6 +566,121 @@ impl Settings {
     }
 }
 
+impl Default for ControllerConfig {
+    fn default() -> Self {
+        ControllerConfig::BangBang {
+            min: None,
+            max: None,
+            reverse: false,
+        }
+    }
+}
+
+impl Default for Failsafe {
+    fn default() -> Self {
+        Failsafe {
+            max_failed_polls: 3,
+            max_reading_age: None,
+            safe_state: HashMap::from([
+                ("heater".to_string(), false),
+                ("irrigator".to_string(), false),
+            ]),
+            limits: Vec::new(),
+        }
+    }
+}
+
+impl Actuators {
+    //Default id of the actuators of this kind
+    pub fn name(&self) -> &'static str {
+        match self {
+            Actuators::Irrigator => "irrigator",
+            Actuators::Heater => "heater",
+            Actuators::Lighting => "lighting",
+            Actuators::UV => "uv",
+            Actuators::Shading => "shading",
+            Actuators::Fan => "fan",
+            Actuators::Humidifier => "humidifier",
+            Actuators::Dehumidifier => "dehumidifier",
+            Actuators::Co2Injector => "co2_injector",
+            Actuators::NutrientPump => "nutrient_pump",
+            Actuators::PhUpPump => "ph_up_pump",
+            Actuators::PhDownPump => "ph_down_pump",
+        }
+    }
+
+    //Variables each actuator affects and whether it lowers them, the ones without any (UV and the
+    //nutrient pump) only follow schedules and pulses
+    //The server doesn't always send a pH range, set explicit limits for the pH pumps in that case
+    pub fn default_targets(&self) -> Vec<(Variable, bool)> {
+        match self {
+            Actuators::Irrigator => vec![(Variable::SoilHumidity, false)],
+            Actuators::Heater => vec![(Variable::Temperature, false)],
+            Actuators::Lighting => vec![(Variable::Luminosity, false)],
+            Actuators::UV => vec![],
+            Actuators::Shading => vec![(Variable::Luminosity, true)],
+            Actuators::Fan => vec![(Variable::Temperature, true), (Variable::AirQuality, true)],
+            Actuators::Humidifier => vec![(Variable::AirHumidity, false)],
+            Actuators::Dehumidifier => vec![(Variable::AirHumidity, true)],
+            Actuators::Co2Injector => vec![(Variable::AirQuality, false)],
+            Actuators::NutrientPump => vec![],
+            Actuators::PhUpPump => vec![(Variable::Ph, false)],
+            Actuators::PhDownPump => vec![(Variable::Ph, true)],
+        }
+    }
+
+    //Channel used by the firmware before channels were configurable, matches the legacy bit order
+    //Kinds added later take the channels after the original five
+    pub fn default_channel(&self) -> u8 {
+        match self {
+            Actuators::Irrigator => 4,
+            Actuators::Heater => 3,
+            Actuators::Lighting => 2,
+            Actuators::UV => 1,
+            Actuators::Shading => 0,
+            Actuators::Fan => 5,
+            Actuators::Humidifier => 6,
+            Actuators::Dehumidifier => 7,
+            Actuators::Co2Injector => 8,
+            Actuators::NutrientPump => 9,
+            Actuators::PhUpPump => 10,
+            Actuators::PhDownPump => 11,
+        }
+    }
+}
+
+impl ActuatorSpec {
+    pub fn new(kind: Actuators, inverted: bool) -> Self {
+        ActuatorSpec {
+            id: kind.name().to_string(),
+            kind,
+            channel: kind.default_channel(),
+            inverted,
+            output: OutputType::Binary,
+        }
+    }
+}
+
+impl From<LegacyIO> for IO {
+    fn from(value: LegacyIO) -> Self {
+        let actuators = value
+            .actuators
+            .into_iter()
+            .map(|a| match a {
+                ActuatorEntry::Kind(kind) => {
+                    ActuatorSpec::new(kind, value.inverted.contains(&kind))
+                }
+                ActuatorEntry::Spec(spec) => spec,
+            })
+            .collect();
+
+        IO {
+            sensors: value.sensors,
+            actuators,
+        }
+    }
+}
+
 //Convert indexes from menu selections to enums
 impl TryFrom<&usize> for Sensors {
     type Error = Error;
@@ -71,6 +693,11 @@ impl TryFrom<&usize> for Sensors {
             4 => Sensors::Luminometer,
             5 => Sensors::Co2,
             6 => Sensors::PH,
+            7 => Sensors::Ec,
+            8 => Sensors::TankLevel,
+            9 => Sensors::SoilThermometer,
+            10 => Sensors::Barometer,
+            11 => Sensors::LeafWetness,
             _ => return Err(Error::new(ErrorKind::InvalidInput, "Out of range value")),
         };
         Ok(res)
@@ -86,6 +713,13 @@ impl TryFrom<&usize> for Actuators {
             2 => Actuators::Lighting,
             3 => Actuators::UV,
             4 => Actuators::Shading,
+            5 => Actuators::Fan,
+            6 => Actuators::Humidifier,
+            7 => Actuators::Dehumidifier,
+            8 => Actuators::Co2Injector,
+            9 => Actuators::NutrientPump,
+            10 => Actuators::PhUpPump,
+            11 => Actuators::PhDownPump,
             _ => return Err(Error::new(ErrorKind::InvalidInput, "Out of range value")),
         };
         Ok(res)
@@ -94,37 +728,33 @@ impl TryFrom<&usize> for Actuators {
 
 impl From<IO> for IOFlags {
     fn from(value: IO) -> Self {
-        let mut ssum: u8 = 0;
+        let mut ssum: u32 = 0;
         for s in value.sensors {
             match s {
+                //Sensors were added after the first five, so they take the higher bits
+                Sensors::LeafWetness => ssum += 2048,
+                Sensors::Barometer => ssum += 1024,
+                Sensors::SoilThermometer => ssum += 512,
+                Sensors::TankLevel => ssum += 256,
+                Sensors::Ec => ssum += 128,
+                Sensors::Hygrometer => ssum += 64,
+                Sensors::Thermometer => ssum += 32,
                 Sensors::DHT11 => ssum += 16,
                 Sensors::SoilHygrometer => ssum += 8,
                 Sensors::Luminometer => ssum += 4,
                 Sensors::Co2 => ssum += 2,
                 Sensors::PH => ssum += 1,
-                _ => {}
             }
         }
 
-        let mut asum: u8 = 0;
+        //Each actuator sets the bit of its channel, the ones that don't fit are refused on load
+        let mut asum: u32 = 0;
+        let mut isum: u32 = 0;
         for a in value.actuators {
-            match a {
-                Actuators::Irrigator => asum += 16,
-                Actuators::Heater => asum += 8,
-                Actuators::Lighting => asum += 4,
-                Actuators::UV => asum += 2,
-                Actuators::Shading => asum += 1,
-            }
-        }
-
-        let mut isum: u8 = 0;
-        for a in value.inverted {
-            match a {
-                Actuators::Irrigator => isum += 16,
-                Actuators::Heater => isum += 8,
-                Actuators::Lighting => isum += 4,
-                Actuators::UV => isum += 2,
-                Actuators::Shading => isum += 1,
+            let bit = 1u32.checked_shl(a.channel as u32).unwrap_or(0);
+            asum |= bit;
+            if a.inverted {
+                isum |= bit;
             }
         }
 
@@ -144,6 +774,144 @@ pub fn load_conf() -> Result<Settings, Box<dyn std::error::Error>> {
         .add_source(File::with_name("/etc/cultiva/settings.toml"))
         .build()?
         .try_deserialize::<Settings>()?;
+    settings.validate()?;
 
     Ok(settings)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::{
+        ActuatorSpec, Actuators, DEFAULT_ZONE, Failsafe, IO, IOFlags, OutputType, Settings,
+        zone_path,
+    };
+    use config::{Config, File, FileFormat};
+
+    fn parse(content: &str) -> IO {
+        Config::builder()
+            .add_source(File::from_str(content, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize::<IO>()
+            .unwrap()
+    }
+
+    #[test]
+    fn legacy_actuators() {
+        let io = parse(
+            r#"
+            sensors = ["DHT11", "Thermometer", "PH"]
+            actuators = ["Irrigator", "Heater"]
+            inverted = ["Irrigator"]
+            "#,
+        );
+
+        assert_eq!(
+            io.actuators[0],
+            ActuatorSpec::new(Actuators::Irrigator, true)
+        );
+        assert_eq!(io.actuators[1].channel, 3);
+        let flags: IOFlags = io.into();
+        assert_eq!(flags.sensors_flag, 49);
+        assert_eq!(flags.actuators_flag, 24);
+        assert_eq!(flags.inverted_flag, 16);
+    }
+
+    #[test]
+    fn actuator_specs() {
+        let io = parse(
+            r#"
+            sensors = []
+
+            [[actuators]]
+            id = "heater_north"
+            kind = "Heater"
+            channel = 6
+            output = "Variable"
+            "#,
+        );
+
+        assert_eq!(io.actuators[0].id, "heater_north");
+        assert_eq!(io.actuators[0].output, OutputType::Variable);
+        assert!(!io.actuators[0].inverted);
+
+        //Channels past the flags are refused instead of left out of them
+        let mut settings = Settings {
+            physical_interface: io,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_ok());
+        settings.physical_interface.actuators[0].channel = 32;
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn zones() {
+        let settings = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                [network]
+                online = false
+
+                [calibration]
+                ph = { type = "Linear", offset = 0.2 }
+                soil_humidity = { type = "TwoPoint", dry = 800, wet = 400 }
+
+                [[zones]]
+                name = "north"
+                camera = 1
+                board = { name = "arduino:avr:uno", port = "/dev/ttyACM0" }
+                physical_interface = { sensors = ["DHT11"], actuators = ["Heater"] }
+                calibration = { soil_humidity = { type = "TwoPoint", dry = 700, wet = 300 } }
+                failsafe = { max_failed_polls = 10 }
+
+                [[zones]]
+                name = "south"
+                board = { name = "arduino:avr:uno", port = "/dev/ttyACM1" }
+                physical_interface = { sensors = [], actuators = [] }
+                "#,
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize::<Settings>()
+            .unwrap();
+
+        assert_eq!(settings.zone(None).unwrap().name, "north");
+        assert_eq!(
+            settings.zone(Some("south")).unwrap().board.port,
+            "/dev/ttyACM1"
+        );
+        assert!(settings.zone(Some("east")).is_err());
+
+        //The zone replaces only what it sets
+        let north = settings.zone(Some("north")).unwrap();
+        let south = settings.zone(Some("south")).unwrap();
+        let profiles = settings.calibration(&north);
+        assert_eq!(profiles["soil_humidity"].apply(700.0), 0.0);
+        assert!(profiles.contains_key("ph"));
+        assert_eq!(
+            settings.calibration(&south)["soil_humidity"].apply(800.0),
+            0.0
+        );
+        assert_eq!(settings.failsafe(&north).max_failed_polls, 10);
+        assert_eq!(
+            settings.failsafe(&south).max_failed_polls,
+            Failsafe::default().max_failed_polls
+        );
+        assert_eq!(Settings::default().zone(None).unwrap().name, DEFAULT_ZONE);
+
+        assert_eq!(
+            zone_path("north", "/var/lib/cultiva/state.json"),
+            "/var/lib/cultiva/zones/north/state.json"
+        );
+        assert_eq!(
+            zone_path("north", "/var/lib/cultiva/captures/"),
+            "/var/lib/cultiva/zones/north/captures/"
+        );
+        assert_eq!(
+            zone_path(DEFAULT_ZONE, "/etc/cultiva/context.toml"),
+            "/etc/cultiva/context.toml"
+        );
+    }
+}
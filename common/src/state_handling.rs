@@ -1,40 +1,163 @@
-use serde::{Deserialize, Serialize};
+use crate::settings::OutputType;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 
-#[derive(Serialize, Deserialize, Default, Copy, Clone)]
-pub struct ActivationState {
-    pub irrigator: Option<bool>,
-    pub heater: Option<bool>,
-    pub lighting: Option<bool>,
-    pub uv: Option<bool>,
-    pub shading: Option<bool>,
+//State of each actuator keyed by its id, actuators that aren't present are left as they are
+//Null values are accepted and ignored, older servers send them for unsupported actuators
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(
+    from = "HashMap<String, Option<Level>>",
+    into = "HashMap<String, Level>"
+)]
+pub struct ActivationState(HashMap<String, Level>);
+
+//Output of an actuator, either true/false or a percentage (values over 100 are taken as 100,
+//negative ones are rejected)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(untagged)]
+pub enum Level {
+    Switch(bool),
+    Percent(#[serde(deserialize_with = "clamp_percent")] u8),
+}
+
+fn clamp_percent<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    Ok(u64::deserialize(deserializer)?.min(100) as u8)
+}
+
+//Optional part of a command, the actuators are reverted once the duration (in seconds) expires
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct PulseOptions {
+    pub duration: u64,
+    //Starts right away when missing
+    pub start: Option<DateTime<Local>>,
+}
+
+impl Level {
+    pub fn percent(&self) -> u8 {
+        match self {
+            Level::Switch(on) => {
+                if *on {
+                    100
+                } else {
+                    0
+                }
+            }
+            Level::Percent(p) => (*p).min(100),
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.percent() > 0
+    }
+
+    //Relays only take on or off, anything above 0 turns them on
+    pub fn for_output(self, output: OutputType) -> Level {
+        match output {
+            OutputType::Binary => Level::Switch(self.is_on()),
+            OutputType::Variable => Level::Percent(self.percent()),
+        }
+    }
+}
+
+impl PartialEq for Level {
+    fn eq(&self, other: &Self) -> bool {
+        self.percent() == other.percent()
+    }
 }
 
 impl ActivationState {
     pub fn new() -> Self {
         Default::default()
     }
+
+    pub fn get(&self, id: &str) -> Option<bool> {
+        self.0.get(id).map(|l| l.is_on())
+    }
+
+    pub fn level(&self, id: &str) -> Option<Level> {
+        self.0.get(id).copied()
+    }
+
+    //None removes the actuator from the state
+    pub fn set(&mut self, id: &str, value: Option<bool>) {
+        self.set_level(id, value.map(Level::Switch));
+    }
+
+    pub fn set_level(&mut self, id: &str, value: Option<Level>) {
+        match value {
+            Some(v) => self.0.insert(id.to_string(), v),
+            None => self.0.remove(id),
+        };
+    }
+
+    //Take every value present in other, leaving the rest untouched
+    pub fn overlay(&mut self, other: ActivationState) {
+        self.0.extend(other.0);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.0.keys().cloned().collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Level)> {
+        self.0.iter()
+    }
+}
+
+impl From<HashMap<String, Option<Level>>> for ActivationState {
+    fn from(value: HashMap<String, Option<Level>>) -> Self {
+        ActivationState(
+            value
+                .into_iter()
+                .filter_map(|(id, v)| v.map(|v| (id, v)))
+                .collect(),
+        )
+    }
 }
 
-impl From<ActivationState> for HashMap<String, bool> {
-    //Map only existing values
+impl<const N: usize> From<[(&str, bool); N]> for ActivationState {
+    fn from(value: [(&str, bool); N]) -> Self {
+        ActivationState(
+            value
+                .iter()
+                .map(|(id, v)| (id.to_string(), Level::Switch(*v)))
+                .collect(),
+        )
+    }
+}
+
+impl From<ActivationState> for HashMap<String, Level> {
     fn from(value: ActivationState) -> Self {
-        let mut hm = HashMap::new();
-        if let Some(irrigator) = value.irrigator {
-            hm.insert("irrigator".to_string(), irrigator);
-        }
-        if let Some(heater) = value.heater {
-            hm.insert("heater".to_string(), heater);
-        }
-        if let Some(lighting) = value.lighting {
-            hm.insert("lighting".to_string(), lighting);
-        }
-        if let Some(uv) = value.uv {
-            hm.insert("uv".to_string(), uv);
-        }
-        if let Some(shading) = value.shading {
-            hm.insert("shading".to_string(), shading);
-        }
-        hm
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::OutputType;
+    use crate::state_handling::{ActivationState, Level};
+
+    #[test]
+    fn levels() {
+        let state: ActivationState =
+            serde_json::from_str(r#"{"heater": true, "lighting": 60, "uv": null}"#).unwrap();
+
+        assert_eq!(state.get("heater"), Some(true));
+        assert_eq!(state.level("lighting"), Some(Level::Percent(60)));
+        assert_eq!(state.get("uv"), None);
+        assert_eq!(
+            Level::Percent(60).for_output(OutputType::Binary),
+            Level::Switch(true)
+        );
+        assert_eq!(Level::Percent(200).percent(), 100);
+
+        let over: ActivationState = serde_json::from_str(r#"{"lighting": 300}"#).unwrap();
+        assert!(matches!(over.level("lighting"), Some(Level::Percent(100))));
+        assert!(serde_json::from_str::<ActivationState>(r#"{"lighting": -20}"#).is_err());
     }
 }
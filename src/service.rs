@@ -1,37 +1,86 @@
+mod board;
 mod capture;
+mod control;
+mod failsafe;
+mod persistence;
+mod sampling;
 mod serial;
+mod simulated;
 mod socket_io;
 pub mod supervision;
 
+use crate::service::board::Modes::{Active, Auto};
+use crate::service::board::{BoardBackend, BoardControl, poll_sensors};
 use crate::service::capture::{get_image_buffer, poll_cam};
-use crate::service::serial::BoardControl;
-use crate::service::serial::Modes::{Active, Auto};
+use crate::service::control::AutoControl;
+use crate::service::simulated::SimulatedBoard;
 use crate::service::socket_io::{
-    authenticate_connection, on_failure, on_success, report_result, send_data, test_connection,
+    authenticate_connection, on_failure, on_success, register_client, report_result, send_data,
+    test_connection,
 };
 use crate::service::supervision::{evaluate, get_assessment, get_ranges};
+use chrono::Local;
 use common::context::{get_context, set_context};
-use common::db_client::{get_readings, insert_reading};
-use common::settings::load_conf;
-use common::state_handling::ActivationState;
+use common::db_client::{
+    HistoryQuery, Reading, get_history, get_readings, insert_reading, migrate,
+};
+use common::retention;
+use common::retention::print_report;
+use common::schedule::{get_schedule, set_schedule};
+use common::serial::SerialLink;
+use common::settings::{Backend, Settings, Zone, load_conf};
+use common::state_handling::{ActivationState, PulseOptions};
 use rust_socketio::{ClientBuilder, Payload, RawClient};
-use serde_json::json;
+use serde_json::{Value, json};
 use std::env::var;
 use std::error::Error;
 use std::io;
-use std::io::ErrorKind::Deadlock;
+use std::io::ErrorKind::{Deadlock, NotFound};
 use std::sync::{Arc, Mutex};
 use std::thread::{sleep, spawn};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio::task::spawn_blocking;
 use tokio_cron_scheduler::{Job, JobScheduler};
 
+//Boards of every zone, in the order the zones appear in the settings
+type Boards = Vec<(String, Arc<Mutex<BoardControl>>)>;
+
+//Zone named by an optional argument of the event, the first one when it's missing. Unknown zones
+//are reported back to the server
+fn event_zone(text: &[Value], index: usize, client: &RawClient, response_id: &str) -> Option<Zone> {
+    let name = text.get(index).and_then(|v| v.as_str());
+    match load_conf().and_then(|config| Ok(config.zone(name)?)) {
+        Ok(zone) => Some(zone),
+        Err(e) => {
+            report_result(client.clone(), response_id, false, &e.to_string());
+            None
+        }
+    }
+}
+
+fn zone_board<'a>(boards: &'a Boards, zone: &Zone) -> Option<&'a Arc<Mutex<BoardControl>>> {
+    boards
+        .iter()
+        .find(|(name, _)| *name == zone.name)
+        .map(|(_, board)| board)
+}
+
+//The second argument is either the amount of latest readings or a history query
 fn on_query(payload: Payload, raw_client: RawClient) {
     if let Payload::Text(text) = &payload
         && text.len() >= 2
         && let Some(response_id) = text[0].as_str()
-        && let Some(amount) = text[1].as_u64()
     {
-        match get_readings(amount) {
+        let Some(zone) = event_zone(text, 2, &raw_client, response_id) else {
+            return;
+        };
+        let result: Result<Vec<Reading>, Box<dyn Error>> = match text[1].as_u64() {
+            Some(amount) => get_readings(&zone.name, amount).map_err(|e| e.into()),
+            None => serde_json::from_value::<HistoryQuery>(text[1].clone())
+                .map_err(|e| e.into())
+                .and_then(|query| Ok(get_history(&zone.name, &query)?)),
+        };
+        match result {
             Ok(readings) => send_data(
                 &raw_client,
                 json!({
@@ -55,8 +104,11 @@ fn on_context(payload: Payload, raw_client: RawClient) {
         && text.len() >= 2
         && let Some(response_id) = text[0].as_str()
     {
+        let Some(zone) = event_zone(text, 2, &raw_client, response_id) else {
+            return;
+        };
         if let Some(_flag) = text[1].as_str() {
-            match get_context() {
+            match get_context(&zone.name) {
                 Ok(context) => send_data(
                     &raw_client,
                     json!({
@@ -75,7 +127,7 @@ fn on_context(payload: Payload, raw_client: RawClient) {
             let message;
             match serde_json::from_value(text[1].clone()) {
                 Ok(context) => {
-                    if let Err(e) = set_context(context) {
+                    if let Err(e) = set_context(&zone.name, context) {
                         status = false;
                         message = e.to_string();
                         eprintln!("{}", t!("context.save_err", error = e));
@@ -97,12 +149,55 @@ fn on_context(payload: Payload, raw_client: RawClient) {
     }
 }
 
+fn on_schedule(payload: Payload, raw_client: RawClient) {
+    if let Payload::Text(text) = &payload
+        && text.len() >= 2
+        && let Some(response_id) = text[0].as_str()
+    {
+        let Some(zone) = event_zone(text, 2, &raw_client, response_id) else {
+            return;
+        };
+        if let Some(_flag) = text[1].as_str() {
+            match get_schedule(&zone.name) {
+                Ok(schedule) => send_data(
+                    &raw_client,
+                    json!({
+                        "id": response_id,
+                        "data": schedule,
+                        "success": true
+                    }),
+                ),
+                Err(e) => {
+                    eprintln!("{}", t!("schedule.load_err", error = e));
+                    report_result(raw_client, response_id, false, &e.to_string());
+                }
+            }
+        } else {
+            let result = serde_json::from_value(text[1].clone())
+                .map_err(|e| e.into())
+                .and_then(|schedule| set_schedule(&zone.name, schedule));
+            match result {
+                Ok(_) => report_result(raw_client, response_id, true, "Success saving schedule"),
+                Err(e) => {
+                    eprintln!("{}", t!("schedule.save_err", error = e));
+                    report_result(raw_client, response_id, false, &e.to_string());
+                }
+            }
+        }
+    } else {
+        eprintln!("{}: {:?}", t!("socket_io.payload_invalid"), payload);
+    }
+}
+
 fn on_capture(payload: Payload, client: RawClient) {
     if let Payload::Text(text) = &payload
         && !text.is_empty()
         && let Some(response_id) = text[0].as_str()
     {
-        match get_image_buffer() {
+        let Some(zone) = event_zone(text, 1, &client, response_id) else {
+            return;
+        };
+        match get_image_buffer(&zone) {
             Ok(buffer) => send_data(
                 &client,
                 json!({
@@ -125,7 +220,10 @@ fn on_assessment(payload: Payload, raw_client: RawClient) {
         && !text.is_empty()
         && let Some(response_id) = text[0].as_str()
     {
-        match get_assessment() {
+        let Some(zone) = event_zone(text, 1, &raw_client, response_id) else {
+            return;
+        };
+        match get_assessment(&zone.name) {
             Ok(data) => send_data(
                 &raw_client,
                 json!({
@@ -144,125 +242,184 @@ fn on_assessment(payload: Payload, raw_client: RawClient) {
     }
 }
 
-fn register_data(board: Arc<Mutex<BoardControl>>) -> io::Error {
+fn register_data(zone: Zone, board: Arc<Mutex<BoardControl>>) -> io::Error {
+    let mut auto_control = AutoControl::new(&zone.physical_interface.actuators, &zone.control);
+    let intervals = match load_conf() {
+        Ok(config) => config.intervals,
+        Err(e) => return io::Error::new(NotFound, e.to_string()),
+    };
     //Added delay because sometimes it starts before finishing initializing the connection
-    sleep(Duration::from_secs(5));
+    sleep(Duration::from_secs(intervals.startup));
 
     //Polling loop with delay
-    let mut cycle = Duration::from_secs(10);
+    let mut cycle = Duration::ZERO;
+    let mut last_stored: Option<Instant> = None;
     loop {
         //Adding sleep before the lock, so the mutex stays available
         sleep(cycle);
-        cycle = Duration::from_secs(10);
+        let intervals = load_conf()
+            .map(|config| config.intervals)
+            .unwrap_or_default();
+        cycle = Duration::from_secs(intervals.control.max(1));
 
-        match board.lock() {
-            Ok(mut locked_board) => match locked_board.poll_sensors() {
-                Ok(read) => {
-                    //Use defined ranges to modify actuators behavior
-                    match get_ranges() {
-                        Ok(ranges) => {
-                            let mut activate = ActivationState::new();
-                            if locked_board.auto_modes.heater.is_some_and(|x| x) {
-                                if read.temperature.is_some_and(|t| t > ranges.temperature.max) {
-                                    activate.heater = Some(false);
-                                } else if read
-                                    .temperature
-                                    .is_some_and(|t| t < ranges.temperature.min)
-                                {
-                                    activate.heater = Some(true);
-                                }
-                            }
-                            if locked_board.auto_modes.irrigator.is_some_and(|x| x) {
-                                if read
-                                    .soil_humidity
-                                    .is_some_and(|i| i > ranges.soil_humidity.max)
-                                {
-                                    activate.irrigator = Some(false);
-                                } else if read
-                                    .soil_humidity
-                                    .is_some_and(|i| i < ranges.soil_humidity.min)
-                                {
-                                    activate.irrigator = Some(true);
-                                }
-                            }
-                            if locked_board.auto_modes.lighting.is_some_and(|x| x) {
-                                if read.luminosity.is_some_and(|l| l > ranges.luminosity.max) {
-                                    activate.lighting = Some(false);
-                                } else if read.luminosity.is_some_and(|l| l < ranges.luminosity.min)
-                                {
-                                    activate.lighting = Some(true);
-                                }
-                            }
-
-                            if let Err(e) = locked_board.set_activation(activate) {
-                                eprintln!("{}", t!("serial.command.error", error = e));
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("{}", t!("supervision.range_err", error = e));
-                        }
-                    }
+        let read = match poll_sensors(&board) {
+            Ok(read) => read,
+            Err(err) => {
+                eprintln!("{}, {}", t!("serial.input_error", error = err), t!("retry"));
+                cycle = Duration::from_secs(intervals.retry);
+                continue;
+            }
+        };
 
-                    match insert_reading(read) {
-                        Ok(_) => {
-                            println!("{}", t!("serial.inserted"));
-                            cycle = Duration::from_mins(30);
-                        }
-                        Err(e) => {
-                            eprintln!("{}. {}", t!("serial.insert_error", error = e), t!("retry"));
-                        }
-                    }
+        match board.lock() {
+            Ok(mut locked_board) => {
+                //Use defined ranges to modify actuators behavior
+                let ranges = get_ranges(&zone.name)
+                    .inspect_err(|e| eprintln!("{}", t!("supervision.range_err", error = e)))
+                    .ok();
+                let mut activate =
+                    auto_control.evaluate(&read, ranges.as_ref(), &locked_board.auto_modes);
+                //Schedules take precedence over the controllers
+                match get_schedule(&zone.name) {
+                    Ok(schedule) => activate
+                        .overlay(schedule.decide(&locked_board.auto_modes, Local::now().time())),
+                    Err(e) => eprintln!("{}", t!("schedule.load_err", error = e)),
                 }
-                Err(err) => {
-                    eprintln!("{}, {}", t!("serial.input_error", error = err), t!("retry"));
+                if let Err(e) = locked_board.set_activation(activate) {
+                    eprintln!("{}", t!("serial.command.error", error = e));
                 }
-            },
+            }
             Err(e) => {
                 return io::Error::new(Deadlock, format!("{}", t!("error.fatal", error = e)));
             }
         }
-    }
-}
 
-async fn supervise(board: Arc<Mutex<BoardControl>>) {
-    println!("{}", t!("supervision.start"));
-    if let Ok(readings) = get_readings(20)
-        && let Ok(context) = get_context()
-        && let Ok(image) = get_image_buffer()
-    {
-        //Prevent mutex being locked while awaiting by wrapping it in this block
-        let eval = match board.lock() {
-            Ok(locked) => evaluate(readings, context, locked.state, image),
+        //Only some of the readings are kept, a failed insert is retried with the next
+        if last_stored.is_some_and(|t| t.elapsed() < Duration::from_secs(intervals.storage)) {
+            continue;
+        }
+        match insert_reading(&zone.name, read) {
+            Ok(_) => {
+                println!("{}", t!("serial.inserted"));
+                last_stored = Some(Instant::now());
+            }
             Err(e) => {
-                eprintln!("{}, {}", t!("serial.lock_error"), e);
-                return;
+                eprintln!("{}. {}", t!("serial.insert_error", error = e), t!("retry"));
             }
+        }
+    }
+}
+
+//Applies schedule windows as soon as they open or close, instead of waiting for the next reading
+fn apply_schedule(zone: &str, board: &Arc<Mutex<BoardControl>>) {
+    let schedule = match get_schedule(zone) {
+        Ok(schedule) => schedule,
+        Err(e) => {
+            eprintln!("{}", t!("schedule.load_err", error = e));
+            return;
+        }
+    };
+
+    let Ok(mut locked) = board.lock() else {
+        eprintln!("{}", t!("serial.lock_error"));
+        return;
+    };
+    let decision = schedule.decide(&locked.auto_modes, Local::now().time());
+
+    //Only send what would actually change
+    let mut command = ActivationState::new();
+    for (name, value) in decision.iter() {
+        if locked.state.get(name) != Some(value.is_on()) {
+            command.set_level(name, Some(*value));
+        }
+    }
+    if command.is_empty() {
+        return;
+    }
+
+    if let Err(e) = locked.set_activation(command) {
+        eprintln!("{}", t!("serial.command.error", error = e));
+    }
+}
+
+//Detects a lost board and reopens it, waiting longer after every failed attempt
+fn watch_connection(board: Arc<Mutex<BoardControl>>) {
+    let mut backoff = Duration::from_secs(2);
+    loop {
+        sleep(backoff);
+
+        let Ok(mut locked) = board.lock() else {
+            eprintln!("{}", t!("serial.lock_error"));
+            return;
         };
+        if locked.is_connected() {
+            backoff = Duration::from_secs(2);
+            continue;
+        }
 
-        match eval.await {
-            Ok(evaluation) => {
-                let Ok(mut locked) = board.lock() else {
-                    eprintln!("{}", t!("serial.lock_error"));
-                    return;
-                };
-                if let Err(e) = locked.set_activation(evaluation) {
-                    eprintln!("{}", t!("serial.command.error", error = e));
-                }
+        match locked.reconnect() {
+            Ok(_) => backoff = Duration::from_secs(2),
+            Err(e) => {
+                backoff = (backoff * 2).min(Duration::from_secs(60));
+                eprintln!(
+                    "{}",
+                    t!(
+                        "serial.reconnect_error",
+                        error = e,
+                        seconds = backoff.as_secs()
+                    )
+                );
             }
+        }
+    }
+}
+
+async fn supervise(zone: Zone, board: Arc<Mutex<BoardControl>>) {
+    println!("{}", t!("supervision.start", zone = zone.name));
+    let data_zone = zone.clone();
+    let data_board = board.clone();
+    let data = spawn_blocking(move || {
+        let readings = get_readings(&data_zone.name, 20).ok()?;
+        let context = get_context(&data_zone.name).ok()?;
+        let image = get_image_buffer(&data_zone).ok()?;
+        match data_board.lock() {
+            Ok(locked) => Some((readings, context, locked.state.clone(), image)),
             Err(e) => {
-                eprintln!("{}", t!("supervision.net_error", error = e));
+                eprintln!("{}, {}", t!("serial.lock_error"), e);
+                None
             }
         }
-    } else {
+    })
+    .await;
+    let Ok(Some((readings, context, state, image))) = data else {
         eprintln!("{}", t!("supervision.no_data"));
-    }
+        return;
+    };
+
+    let evaluation = match evaluate(&zone.name, readings, context, state, image).await {
+        Ok(evaluation) => evaluation,
+        Err(e) => {
+            eprintln!("{}", t!("supervision.net_error", error = e));
+            return;
+        }
+    };
+    run_blocking(move || {
+        let Ok(mut locked) = board.lock() else {
+            eprintln!("{}", t!("serial.lock_error"));
+            return;
+        };
+        if let Err(e) = locked.set_activation(evaluation) {
+            eprintln!("{}", t!("serial.command.error", error = e));
+        }
+    })
+    .await;
 }
 
-fn initiate_socket(board: Option<Arc<Mutex<BoardControl>>>) {
+fn initiate_socket(boards: Boards) {
     //Retry five times to establish initial connection
     for i in 1..5 {
-        let comm_arc = board.clone();
-        let act_arc = board.clone();
+        let comm_boards = boards.clone();
+        let act_boards = boards.clone();
 
         //Callback to pass the port value to the command handling function
         let command_callback = move |payload: Payload, socket: RawClient| {
@@ -272,12 +429,29 @@ fn initiate_socket(board: Option<Arc<Mutex<BoardControl>>>) {
                 && let Some(mode) = text[1].as_str()
                 && let Ok(command) = serde_json::from_value::<ActivationState>(text[2].clone())
             {
-                if let Some(board) = &comm_arc
+                //A fourth argument turns the command into a timed pulse
+                let pulse = match text.get(3).filter(|p| !p.is_null()) {
+                    Some(options) => {
+                        match serde_json::from_value::<PulseOptions>(options.clone()) {
+                            Ok(options) => Some(options),
+                            Err(e) => {
+                                report_result(socket, response_id, false, &e.to_string());
+                                return;
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                //After the pulse, which can be null
+                let Some(zone) = event_zone(text, 4, &socket, response_id) else {
+                    return;
+                };
+                if let Some(board) = zone_board(&comm_boards, &zone)
                     && let Ok(mut locked) = board.lock()
                 {
                     let result = match mode {
                         "auto" => locked.set_auto_modes(command),
-                        _ => locked.set_activation(command),
+                        _ => locked.command(command, pulse),
                     };
                     match result {
                         Ok(_) => {
@@ -309,7 +483,10 @@ fn initiate_socket(board: Option<Arc<Mutex<BoardControl>>>) {
                 && let Some(response_id) = text[0].as_str()
                 && let Some(mode) = text[1].as_str()
             {
-                if let Some(board) = &act_arc
+                let Some(zone) = event_zone(text, 2, &client, response_id) else {
+                    return;
+                };
+                if let Some(board) = zone_board(&act_boards, &zone)
                     && let Ok(locked) = board.lock()
                 {
                     let info = match mode {
@@ -321,6 +498,8 @@ fn initiate_socket(board: Option<Arc<Mutex<BoardControl>>>) {
                         json!({
                             "id": response_id,
                             "data": locked.get_activation(info),
+                            "pulses": locked.pulse_status(),
+                            "zone": zone.name,
                             "success": true
                         }),
                     )
@@ -349,12 +528,14 @@ fn initiate_socket(board: Option<Arc<Mutex<BoardControl>>>) {
         .on("authenticate", authenticate_connection)
         .on("capture", on_capture)
         .on("context", on_context)
+        .on("schedule", on_schedule)
         .on("assessment", on_assessment)
         .reconnect(true)
         .reconnect_on_disconnect(true)
         .connect()
         {
             Ok(connection) => {
+                register_client(connection.clone());
                 test_connection(connection);
                 return;
             }
@@ -367,62 +548,228 @@ fn initiate_socket(board: Option<Arc<Mutex<BoardControl>>>) {
     eprintln!("{}", t!("socket_io.disable"));
 }
 
+//Board of the zone, one that can't be reached or refused the handshake starts disconnected
+fn start_board(zone: &Zone, config: &Settings) -> Arc<Mutex<BoardControl>> {
+    println!("{}", t!("zones.starting", zone = zone.name));
+    let backend: Box<dyn BoardBackend> = match zone.board.backend {
+        Backend::Simulated => {
+            println!("{}", t!("serial.simulated"));
+            Box::new(SimulatedBoard::new(zone.physical_interface.clone()))
+        }
+        Backend::Serial => {
+            println!("{}", t!("serial.initializing", port = zone.board.port));
+            Box::new(SerialLink::new(
+                &zone.board,
+                zone.physical_interface.sensors.clone(),
+            ))
+        }
+    };
+
+    let mut board = BoardControl::new(backend, zone, config);
+    //A disconnected board is handshaken by the connection supervisor once it shows up
+    if board.is_connected()
+        && let Err(e) = board.handshake(
+            zone.physical_interface.clone().into(),
+            zone.board.on_mismatch,
+        )
+    {
+        //Left disconnected so the connection supervisor keeps retrying the handshake
+        eprintln!("{}", t!("serial.init_error", error = e));
+        board.disconnect();
+    }
+
+    //Without a connection the restored state is sent once the board is reopened
+    if let Err(e) = board.restore(config.persistence.restore) {
+        eprintln!("{}", t!("serial.command.error", error = e));
+    }
+    Arc::new(Mutex::new(board))
+}
+
+//The board, the database and the camera block, so the jobs use them from the blocking threads and
+//leave the runtime ones free for the rest of the jobs
+async fn run_blocking(work: impl FnOnce() + Send + 'static) {
+    if let Err(e) = spawn_blocking(work).await {
+        eprintln!("{}", t!("sched.job_failed", error = e));
+    }
+}
+
+//Reading loop, connection supervisor and periodic jobs of a zone
+async fn schedule_zone(
+    sched: &JobScheduler,
+    zone: Zone,
+    board: Arc<Mutex<BoardControl>>,
+) -> Result<(), Box<dyn Error>> {
+    let reg_board = board.clone();
+    let reg_zone = zone.clone();
+    spawn(move || register_data(reg_zone, reg_board));
+
+    let watch_board = board.clone();
+    spawn(move || watch_connection(watch_board));
+
+    let schedule_board = board.clone();
+    let schedule_zone = zone.name.clone();
+    sched
+        .add(Job::new_async_tz(
+            "*/5 * * * * *",
+            chrono::Local,
+            move |_, _| {
+                let schedule_board = schedule_board.clone();
+                let schedule_zone = schedule_zone.clone();
+                Box::pin(run_blocking(move || {
+                    apply_schedule(&schedule_zone, &schedule_board)
+                }))
+            },
+        )?)
+        .await?;
+
+    //Every second so pulses last close to what was requested
+    let pulse_board = board.clone();
+    sched
+        .add(Job::new_async_tz(
+            "* * * * * *",
+            chrono::Local,
+            move |_, _| {
+                let pulse_board = pulse_board.clone();
+                Box::pin(run_blocking(move || match pulse_board.lock() {
+                    Ok(mut locked) => {
+                        if let Err(e) = locked.update_pulses() {
+                            eprintln!("{}", t!("serial.command.error", error = e));
+                        }
+                    }
+                    Err(e) => eprintln!("{}, {}", t!("serial.lock_error"), e),
+                }))
+            },
+        )?)
+        .await?;
+
+    //Checked often so a stuck actuator is turned off close to its maximum runtime, and so the
+    //failsafe trips soon after the readings get too old
+    let runtime_board = board.clone();
+    sched
+        .add(Job::new_async_tz(
+            "*/10 * * * * *",
+            chrono::Local,
+            move |_, _| {
+                let runtime_board = runtime_board.clone();
+                Box::pin(run_blocking(move || match runtime_board.lock() {
+                    Ok(mut locked) => {
+                        if let Err(e) = locked.enforce_runtime() {
+                            eprintln!("{}", t!("serial.command.error", error = e));
+                        }
+                        if let Err(e) = locked.check_failsafe() {
+                            eprintln!("{}", t!("serial.command.error", error = e));
+                        }
+                    }
+                    Err(e) => eprintln!("{}, {}", t!("serial.lock_error"), e),
+                }))
+            },
+        )?)
+        .await?;
+
+    sched
+        .add(Job::new_async_tz(
+            "0 0 12 * * *",
+            chrono::Local,
+            move |_, _| {
+                let sup_board = board.clone();
+                let sup_zone = zone.clone();
+                Box::pin(async {
+                    supervise(sup_zone, sup_board).await;
+                })
+            },
+        )?)
+        .await?;
+
+    Ok(())
+}
+
+fn apply_retention() {
+    println!("{}", t!("retention.start"));
+    match retention::run(false) {
+        Ok(report) => print_report(&report),
+        Err(e) => eprintln!("{}", t!("retention.failed", error = e)),
+    }
+}
+
 pub(super) async fn start_tasks() -> Result<(), Box<dyn Error>> {
     println!("{}", t!("config.load"));
     let config = load_conf()?;
-
-    println!("{}", t!("serial.initializing", port = config.board.port));
-    let board_arc = match serialport::new(config.board.port, 9600)
-        .timeout(Duration::from_secs(5))
-        .open()
-    {
-        Ok(port) => Some(Arc::new(Mutex::new(BoardControl::new(port)))),
-        Err(e) => {
-            eprintln!("{}", t!("serial.init_error", error = e));
-            None
+    //Upgraded installations get the tables and columns added since they were set up
+    match migrate() {
+        Ok((from, to)) if from < to => {
+            println!("{}", t!("database.migrated", from = from, to = to))
         }
-    };
+        Ok(_) => {}
+        Err(e) => eprintln!("{}", t!("db_panic", error = e)),
+    }
+
+    let zones = config.zones();
+    let boards: Boards = zones
+        .iter()
+        .map(|zone| (zone.name.clone(), start_board(zone, &config)))
+        .collect();
 
-    if let Some(board) = board_arc.clone() {
-        println!("{}", t!("sched.start"));
-        let sched = JobScheduler::new().await?;
-
-        let reg_board = board.clone();
-        spawn(move || register_data(reg_board));
-
-        sched
-            .add(Job::new_async_tz(
-                "0 0 12 * * *",
-                chrono::Local,
-                move |_, _| {
-                    let sup_board = board.clone();
-                    Box::pin(async {
-                        supervise(sup_board).await;
-                    })
-                },
-            )?)
-            .await?;
-
-        sched.start().await?;
+    println!("{}", t!("sched.start"));
+    let sched = JobScheduler::new().await?;
+    for zone in &zones {
+        if let Some(board) = zone_board(&boards, zone) {
+            schedule_zone(&sched, zone.clone(), board.clone()).await?;
+        }
     }
+    sched
+        .add(Job::new_async_tz(
+            config.retention.schedule.as_str(),
+            chrono::Local,
+            |_, _| Box::pin(run_blocking(apply_retention)),
+        )?)
+        .await?;
+    sched.start().await?;
 
-    spawn(move || initiate_socket(board_arc.clone()));
+    spawn(move || initiate_socket(boards));
 
-    poll_cam();
+    poll_cam(zones);
 
     Ok(())
 }
 
 #[tokio::test]
 async fn test_supervision() {
-    //Change it accordingly
-    let device = "/dev/ttyUSB0";
-    let port = serialport::new(device, 9600)
-        .timeout(Duration::from_secs(5))
-        .open()
-        .unwrap();
+    //Runs against the greenhouse model, no microcontroller required
+    let config = load_conf().unwrap_or_default();
+    let zone = config.zone(None).unwrap_or_default();
+    let board = BoardControl::new(
+        Box::new(SimulatedBoard::new(zone.physical_interface.clone())),
+        &zone,
+        &config,
+    );
+
+    supervise(zone, Arc::new(Mutex::new(board))).await;
+}
 
-    supervise(Arc::new(Mutex::new(BoardControl::new(port)))).await;
+#[test]
+fn test_simulated_board() {
+    use common::settings::Sensors;
+    use common::state_handling::ActivationState;
+
+    let io = common::settings::IO {
+        sensors: vec![Sensors::DHT11, Sensors::SoilHygrometer],
+        ..Default::default()
+    };
+    let board = Mutex::new(BoardControl::new(
+        Box::new(SimulatedBoard::new(io)),
+        &Zone::default(),
+        &Settings::default(),
+    ));
+
+    let read = poll_sensors(&board).unwrap();
+    assert!(read.temperature.is_some() && read.soil_humidity.is_some());
+    assert!(read.luminosity.is_none());
+
+    board
+        .lock()
+        .unwrap()
+        .set_activation(ActivationState::new())
+        .unwrap();
 }
 
 #[tokio::test]
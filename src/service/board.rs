@@ -0,0 +1,843 @@
+use crate::service::failsafe::{Event, FailsafeMonitor, record};
+use crate::service::persistence::{SavedPulse, SavedState, load_state, save_state};
+use crate::service::sampling::Sampler;
+use chrono::{DateTime, Local};
+use common::calibration::{Calibration, calibrate};
+use common::db_client::Reading;
+use common::protocol::{FirmwareInfo, PROTOCOL_VERSION};
+use common::settings::{
+    ActuatorControl, ActuatorSpec, IOFlags, MismatchPolicy, RestorePolicy, Settings, Zone,
+};
+use common::state_handling::{ActivationState, Level, PulseOptions};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::io::ErrorKind::{Deadlock, InvalidData};
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+//Anything capable of driving the actuators and reading the sensors, real or not
+pub(super) trait BoardBackend: Send {
+    //Firmware information, None when the backend can't report it (legacy protocol)
+    fn identify(&mut self) -> Result<Option<FirmwareInfo>, Box<dyn Error>>;
+
+    //Each output is an actuator channel and its percentage (0 is off, 100 is on)
+    fn send_outputs(&mut self, outputs: &[(u8, u8)]) -> Result<(), Box<dyn Error>>;
+
+    fn poll_sensors(&mut self) -> Result<Reading, Box<dyn Error>>;
+
+    fn is_connected(&self) -> bool {
+        true
+    }
+
+    fn disconnect(&mut self) {}
+
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+//Timed command, the actuator goes back to the level it had before once it expires
+//The wall clock times are kept only to save the pulse
+struct Pulse {
+    state: Level,
+    //Level when the pulse started, None until it does
+    previous: Option<Level>,
+    start: Instant,
+    duration: Duration,
+    started: Option<Instant>,
+    start_at: DateTime<Local>,
+    started_at: Option<DateTime<Local>>,
+}
+
+//Reported through the activation event, times in seconds
+#[derive(Serialize)]
+pub(super) struct PulseStatus {
+    state: Level,
+    starts_in: u64,
+    remaining: u64,
+}
+
+pub(super) struct BoardControl {
+    backend: Box<dyn BoardBackend>,
+    pub(super) zone: String,
+    //Firmware flags expected by the settings of the zone, checked again after reconnecting
+    expected: IOFlags,
+    on_mismatch: MismatchPolicy,
+    actuators: Vec<ActuatorSpec>,
+    control: HashMap<String, ActuatorControl>,
+    //Last time each actuator was switched, missing ones can change right away
+    switched: HashMap<String, Instant>,
+    //Actuators kept off after reaching their maximum runtime, until the given time
+    locked_out: HashMap<String, Instant>,
+    pulses: HashMap<String, Pulse>,
+    failsafe: FailsafeMonitor,
+    calibration: HashMap<String, Calibration>,
+    sampler: Sampler,
+    //States forced by the failsafe, applied on top of any command
+    interlocks: ActivationState,
+    //Last content written to the state file, None until the saved state has been restored
+    saved: Option<String>,
+    pub(super) state: ActivationState,
+    pub(super) auto_modes: ActivationState,
+}
+
+pub(super) enum Modes {
+    Active,
+    Auto,
+}
+
+impl BoardControl {
+    pub(super) fn new(backend: Box<dyn BoardBackend>, zone: &Zone, settings: &Settings) -> Self {
+        //Set only supported actuators, otherwise None
+        let mut auto = ActivationState::new();
+        let mut active = ActivationState::new();
+        let actuators = zone.physical_interface.actuators.clone();
+        for a in &actuators {
+            auto.set(&a.id, Some(true));
+            active.set_level(&a.id, Some(Level::Switch(false).for_output(a.output)));
+        }
+
+        let calibration = settings.calibration(zone);
+
+        BoardControl {
+            backend,
+            zone: zone.name.clone(),
+            expected: zone.physical_interface.clone().into(),
+            on_mismatch: zone.board.on_mismatch,
+            actuators,
+            control: zone.control.clone(),
+            switched: HashMap::new(),
+            locked_out: HashMap::new(),
+            pulses: HashMap::new(),
+            failsafe: FailsafeMonitor::new(settings.failsafe(zone)),
+            sampler: Sampler::new(settings.sampling(zone), &calibration),
+            calibration,
+            interlocks: ActivationState::new(),
+            saved: None,
+            state: active,
+            auto_modes: auto,
+        }
+    }
+
+    pub(super) fn get_activation(&self, mode: Modes) -> HashMap<String, Level> {
+        match mode {
+            Modes::Active => self.state.clone().into(),
+            Modes::Auto => self.auto_modes.clone().into(),
+        }
+    }
+
+    //Ask the firmware what it was compiled with and compare it against the settings
+    pub(super) fn handshake(
+        &mut self,
+        expected: IOFlags,
+        policy: MismatchPolicy,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut mismatches = Vec::new();
+        match self.backend.identify() {
+            Ok(None) => {
+                println!("{}", t!("serial.handshake.legacy"));
+                return Ok(());
+            }
+            Ok(Some(info)) => {
+                println!(
+                    "{}",
+                    t!(
+                        "serial.handshake.info",
+                        version = info.version,
+                        protocol = info.protocol_version
+                    )
+                );
+
+                if info.protocol_version != PROTOCOL_VERSION {
+                    mismatches.push(t!(
+                        "serial.handshake.protocol",
+                        expected = PROTOCOL_VERSION,
+                        found = info.protocol_version
+                    ));
+                }
+                let flags = [
+                    ("SENSORS", expected.sensors_flag, info.flags.sensors_flag),
+                    (
+                        "ACTUATORS",
+                        expected.actuators_flag,
+                        info.flags.actuators_flag,
+                    ),
+                    ("INVERT", expected.inverted_flag, info.flags.inverted_flag),
+                ];
+                for (name, expected, found) in flags {
+                    if expected != found {
+                        mismatches.push(t!(
+                            "serial.handshake.flag",
+                            flag = name,
+                            expected = expected,
+                            found = found
+                        ));
+                    }
+                }
+            }
+            Err(e) => mismatches.push(t!("serial.handshake.no_answer", error = e)),
+        }
+
+        if mismatches.is_empty() {
+            println!("{}", t!("serial.handshake.success"));
+            return Ok(());
+        }
+        for m in &mismatches {
+            eprintln!("{}", t!("serial.handshake.mismatch", detail = m));
+        }
+
+        match policy {
+            MismatchPolicy::Warn => {
+                eprintln!("{}", t!("serial.handshake.compile_hint"));
+                Ok(())
+            }
+            MismatchPolicy::Refuse => Err(Box::new(std::io::Error::new(
+                InvalidData,
+                t!("serial.handshake.refused"),
+            ))),
+        }
+    }
+
+    pub(super) fn is_connected(&self) -> bool {
+        self.backend.is_connected()
+    }
+
+    pub(super) fn disconnect(&mut self) {
+        self.backend.disconnect();
+    }
+
+    //Reopen the connection and bring the board back to the state it had before losing it
+    pub(super) fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.backend.reconnect()?;
+
+        if let Err(e) = self.handshake(self.expected, self.on_mismatch) {
+            self.disconnect();
+            return Err(e);
+        }
+
+        let outputs = self.outputs();
+        self.backend.send_outputs(&outputs)
+    }
+
+    //Changes the state only for actuators present in both, unknown ids are ignored
+    fn mutate_to_spec(state: &mut ActivationState, spec: ActivationState) {
+        for (id, value) in spec.iter() {
+            if state.get(id).is_some() {
+                state.set_level(id, Some(*value));
+            }
+        }
+    }
+
+    pub(super) fn set_auto_modes(
+        &mut self,
+        command: ActivationState,
+    ) -> Result<(), Box<dyn Error>> {
+        Self::mutate_to_spec(&mut self.auto_modes, command);
+        self.persist();
+
+        Ok(())
+    }
+
+    //Drop the changes that would switch an actuator before its minimum on or off time
+    fn hold_switching(&self, command: &mut ActivationState) {
+        for name in command.ids() {
+            let (Some(requested), Some(current)) = (command.get(&name), self.state.get(&name))
+            else {
+                continue;
+            };
+            if requested == current {
+                continue;
+            }
+            let (Some(control), Some(switched)) =
+                (self.control.get(&name), self.switched.get(&name))
+            else {
+                continue;
+            };
+
+            let minimum = Duration::from_secs(if current {
+                control.min_on
+            } else {
+                control.min_off
+            });
+            let elapsed = switched.elapsed();
+            if elapsed < minimum {
+                println!(
+                    "{}",
+                    t!(
+                        "control.held",
+                        actuator = name,
+                        seconds = (minimum - elapsed).as_secs()
+                    )
+                );
+                command.set(&name, None);
+            }
+        }
+    }
+
+    //Turn on or off the different actuators, running pulses can only be replaced by another command
+    pub(super) fn set_activation(
+        &mut self,
+        mut command: ActivationState,
+    ) -> Result<(), Box<dyn Error>> {
+        for (name, pulse) in &self.pulses {
+            if pulse.started.is_some() {
+                command.set(name, None);
+            }
+        }
+        let now = Instant::now();
+        for (name, until) in &self.locked_out {
+            if now < *until && command.get(name) == Some(true) {
+                command.set(name, None);
+            }
+        }
+        self.hold_switching(&mut command);
+        self.apply(command)
+    }
+
+    //Command sent by a user, replaces any pulse on the same actuators
+    pub(super) fn command(
+        &mut self,
+        command: ActivationState,
+        pulse: Option<PulseOptions>,
+    ) -> Result<(), Box<dyn Error>> {
+        for name in command.ids() {
+            self.pulses.remove(&name);
+        }
+
+        let Some(options) = pulse else {
+            self.persist();
+            return self.set_activation(command);
+        };
+        let now = Instant::now();
+        let wall = Local::now();
+        let start_at = options.start.unwrap_or(wall).max(wall);
+        for (name, state) in command.iter() {
+            if self.state.get(name).is_some() {
+                self.pulses.insert(
+                    name.clone(),
+                    Pulse {
+                        state: *state,
+                        previous: None,
+                        start: to_instant(start_at, now, wall),
+                        duration: Duration::from_secs(options.duration),
+                        started: None,
+                        start_at,
+                        started_at: None,
+                    },
+                );
+            }
+        }
+        self.persist();
+        self.update_pulses()
+    }
+
+    //Start the pulses that are due and revert the expired ones
+    pub(super) fn update_pulses(&mut self) -> Result<(), Box<dyn Error>> {
+        let now = Instant::now();
+        let mut starting = ActivationState::new();
+        let mut ending = ActivationState::new();
+        for (name, pulse) in &self.pulses {
+            match pulse.started {
+                None if now >= pulse.start => starting.set_level(name, Some(pulse.state)),
+                Some(started) if now - started >= pulse.duration => {
+                    let opposite = Level::Switch(!pulse.state.is_on());
+                    ending.set_level(name, Some(pulse.previous.unwrap_or(opposite)))
+                }
+                _ => {}
+            }
+        }
+
+        //A held pulse waits for the relay protection, its duration counts from the actual start
+        self.hold_switching(&mut starting);
+        for (name, state) in starting.iter() {
+            if let Some(pulse) = self.pulses.get_mut(name) {
+                pulse.previous = self.state.level(name);
+                pulse.started = Some(now);
+                pulse.started_at = Some(Local::now());
+                println!(
+                    "{}",
+                    t!(
+                        "control.pulse_started",
+                        actuator = name,
+                        level = state.percent(),
+                        seconds = pulse.duration.as_secs()
+                    )
+                );
+            }
+        }
+        for name in ending.ids() {
+            self.pulses.remove(&name);
+            println!("{}", t!("control.pulse_finished", actuator = name));
+        }
+
+        starting.overlay(ending);
+        if starting.is_empty() {
+            return Ok(());
+        }
+        self.persist();
+        self.apply(starting)
+    }
+
+    pub(super) fn pulse_status(&self) -> HashMap<String, PulseStatus> {
+        let now = Instant::now();
+        self.pulses
+            .iter()
+            .map(|(name, pulse)| {
+                let end = pulse.started.unwrap_or(pulse.start.max(now)) + pulse.duration;
+                let seconds = |t: Instant| t.saturating_duration_since(now).as_secs_f32().ceil();
+                let status = PulseStatus {
+                    state: pulse.state,
+                    starts_in: seconds(pulse.start) as u64,
+                    remaining: seconds(end) as u64,
+                };
+                (name.clone(), status)
+            })
+            .collect()
+    }
+
+    fn apply(&mut self, mut command: ActivationState) -> Result<(), Box<dyn Error>> {
+        command.overlay(self.interlocks.clone());
+        let previous = self.state.clone();
+        Self::mutate_to_spec(&mut self.state, command);
+        for a in &self.actuators {
+            if let Some(level) = self.state.level(&a.id) {
+                self.state
+                    .set_level(&a.id, Some(level.for_output(a.output)));
+            }
+        }
+
+        let outputs = self.outputs();
+        if let Err(e) = self.backend.send_outputs(&outputs) {
+            self.state = previous;
+            return Err(e);
+        }
+
+        for name in self.state.ids() {
+            if previous.get(&name) != self.state.get(&name) {
+                self.switched.insert(name, Instant::now());
+            }
+        }
+        self.persist();
+        Ok(())
+    }
+
+    fn snapshot(&self) -> SavedState {
+        let pulses = self
+            .pulses
+            .iter()
+            .map(|(name, pulse)| {
+                let saved = SavedPulse {
+                    state: pulse.state,
+                    start: pulse.start_at,
+                    duration: pulse.duration.as_secs(),
+                    started: pulse.started_at,
+                    previous: pulse.previous,
+                };
+                (name.clone(), saved)
+            })
+            .collect();
+
+        SavedState {
+            state: self.state.clone(),
+            auto_modes: self.auto_modes.clone(),
+            pulses,
+        }
+    }
+
+    //Write the state file, only when something changed since the last time
+    fn persist(&mut self) {
+        let Some(saved) = &self.saved else {
+            return;
+        };
+        match serde_json::to_string(&self.snapshot()) {
+            Ok(content) if &content != saved => {
+                save_state(&self.zone, &content);
+                self.saved = Some(content);
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("{}", t!("state.save_err", error = e)),
+        }
+    }
+
+    fn restore_from(&mut self, saved: SavedState) {
+        //Actuators that aren't supported anymore stay as None
+        Self::mutate_to_spec(&mut self.state, saved.state);
+        Self::mutate_to_spec(&mut self.auto_modes, saved.auto_modes);
+
+        let now = Instant::now();
+        let wall = Local::now();
+        for (name, pulse) in saved.pulses {
+            if self.state.get(&name).is_none() {
+                continue;
+            }
+            self.pulses.insert(
+                name,
+                Pulse {
+                    state: pulse.state,
+                    previous: pulse.previous,
+                    start: to_instant(pulse.start, now, wall),
+                    duration: Duration::from_secs(pulse.duration),
+                    started: pulse.started.map(|t| to_instant(t, now, wall)),
+                    start_at: pulse.start,
+                    started_at: pulse.started,
+                },
+            );
+        }
+    }
+
+    //Bring back what was saved before the last stop (or not, depending on the policy), send it to
+    //the board and start saving every change
+    pub(super) fn restore(&mut self, policy: RestorePolicy) -> Result<(), Box<dyn Error>> {
+        match policy {
+            RestorePolicy::Last => match load_state(&self.zone) {
+                Ok(Some(saved)) => {
+                    self.restore_from(saved);
+                    println!("{}", t!("state.restored"));
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("{}", t!("state.load_err", error = e)),
+            },
+            RestorePolicy::SafeDefaults => println!("{}", t!("state.defaults")),
+        }
+
+        self.saved = Some(String::new());
+        self.persist();
+        self.apply(ActivationState::new())?;
+        self.update_pulses()
+    }
+
+    //Turn off actuators that have been running longer than allowed, ignoring their minimum on time,
+    //and keep them off during their lockout
+    pub(super) fn enforce_runtime(&mut self) -> Result<(), Box<dyn Error>> {
+        let now = Instant::now();
+        self.locked_out.retain(|name, until| {
+            let locked = now < *until;
+            if !locked {
+                println!("{}", t!("control.released", actuator = name));
+            }
+            locked
+        });
+
+        let mut command = ActivationState::new();
+        for name in self.state.ids() {
+            if self.state.get(&name) == Some(true)
+                && let Some(control) = self.control.get(&name)
+                && let Some(max) = control.max_runtime
+                && let Some(switched) = self.switched.get(&name)
+                && switched.elapsed() >= Duration::from_secs(max)
+            {
+                let lockout = control.lockout.unwrap_or(max).max(control.min_off);
+                println!(
+                    "{}",
+                    t!(
+                        "control.max_runtime",
+                        actuator = name,
+                        seconds = max,
+                        lockout = lockout
+                    )
+                );
+                self.locked_out
+                    .insert(name.clone(), now + Duration::from_secs(lockout));
+                command.set(&name, Some(false));
+            }
+        }
+
+        if command.is_empty() {
+            return Ok(());
+        }
+        self.apply(command)
+    }
+
+    //Board channel and percentage of each actuator
+    fn outputs(&self) -> Vec<(u8, u8)> {
+        self.actuators
+            .iter()
+            .filter_map(|a| {
+                self.state
+                    .level(&a.id)
+                    .map(|level| (a.channel, level.percent()))
+            })
+            .collect()
+    }
+
+    fn poll_sample(&mut self) -> Result<Reading, Box<dyn Error>> {
+        let mut read = self.backend.poll_sensors()?;
+        //Everything downstream, limits included, works with calibrated values
+        calibrate(&mut read, &self.calibration);
+        Ok(read)
+    }
+
+    //Reading made of the samples, the poll counts as failed when there are none
+    fn combine_samples(
+        &mut self,
+        samples: Vec<Reading>,
+        error: Option<Box<dyn Error>>,
+    ) -> Result<Reading, Box<dyn Error>> {
+        if samples.is_empty()
+            && let Some(e) = error
+        {
+            let events = self.failsafe.failed_poll();
+            if let Err(e) = self.update_interlocks(events) {
+                eprintln!("{}", t!("serial.command.error", error = e));
+            }
+            return Err(e);
+        }
+
+        let (read, rejected) = self.sampler.combine(&samples, Instant::now());
+        for r in rejected {
+            eprintln!(
+                "{}",
+                t!(
+                    "sampling.rejected",
+                    variable = format!("{:?}", r.variable),
+                    value = r.value,
+                    reason = r.reason
+                )
+            );
+        }
+        let events = self.failsafe.reading(&read);
+        if let Err(e) = self.update_interlocks(events) {
+            eprintln!("{}", t!("serial.command.error", error = e));
+        }
+        Ok(read)
+    }
+
+    //Trip the failsafe if the last reading is too old, and retry forcing states that didn't reach
+    //the board
+    pub(super) fn check_failsafe(&mut self) -> Result<(), Box<dyn Error>> {
+        let events = self.failsafe.check_age();
+        self.update_interlocks(events)
+    }
+
+    fn update_interlocks(&mut self, events: Vec<Event>) -> Result<(), Box<dyn Error>> {
+        for e in &events {
+            record(&self.zone, e);
+        }
+
+        self.interlocks = self.failsafe.interlocks();
+        for name in self.interlocks.ids() {
+            self.pulses.remove(&name);
+        }
+        self.persist();
+
+        let pending = self.interlocks.iter().any(|(name, state)| {
+            self.state
+                .level(name)
+                .is_some_and(|current| current != *state)
+        });
+        if !pending {
+            return Ok(());
+        }
+        self.apply(ActivationState::new())
+    }
+}
+
+//Several polls combined into a single reading, only failing if all of them do. The board is locked
+//only while polling, other jobs can use it between samples
+pub(super) fn poll_sensors(board: &Mutex<BoardControl>) -> Result<Reading, Box<dyn Error>> {
+    let lock = || {
+        board
+            .lock()
+            .map_err(|_| io::Error::new(Deadlock, t!("serial.lock_error")))
+    };
+    let (count, spacing) = {
+        let locked = lock()?;
+        (locked.sampler.samples(), locked.sampler.spacing())
+    };
+
+    let mut samples = Vec::new();
+    let mut error = None;
+    for i in 0..count {
+        if i > 0 {
+            sleep(spacing);
+        }
+        match lock()?.poll_sample() {
+            Ok(read) => samples.push(read),
+            Err(e) => error = Some(e),
+        }
+    }
+
+    lock()?.combine_samples(samples, error)
+}
+
+//Instant matching a wall clock time, times before the start of the process are clamped to now
+fn to_instant(time: DateTime<Local>, now: Instant, wall: DateTime<Local>) -> Instant {
+    match (time - wall).to_std() {
+        Ok(ahead) => now + ahead,
+        Err(_) => (wall - time)
+            .to_std()
+            .ok()
+            .and_then(|behind| now.checked_sub(behind))
+            .unwrap_or(now),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::service::board::BoardControl;
+    use crate::service::simulated::SimulatedBoard;
+    use common::settings::{
+        ActuatorControl, ActuatorSpec, Actuators, IO, OutputType, Settings, Zone,
+    };
+    use common::state_handling::{ActivationState, Level, PulseOptions};
+    use std::time::{Duration, Instant};
+
+    fn heater_board(control: ActuatorControl) -> BoardControl {
+        let mut board = BoardControl::new(
+            Box::new(SimulatedBoard::new(IO::default())),
+            &Zone::default(),
+            &Settings::default(),
+        );
+        board.actuators = vec![ActuatorSpec::new(Actuators::Heater, false)];
+        board.state.set("heater", Some(false));
+        board.auto_modes.set("heater", Some(true));
+        board.control.insert("heater".to_string(), control);
+        board
+    }
+
+    fn heater(on: bool) -> ActivationState {
+        ActivationState::from([("heater", on)])
+    }
+
+    #[test]
+    fn minimum_on_time() {
+        let mut board = heater_board(ActuatorControl {
+            min_on: 60,
+            ..Default::default()
+        });
+
+        board.set_activation(heater(true)).unwrap();
+        board.set_activation(heater(false)).unwrap();
+        assert_eq!(board.state.get("heater"), Some(true));
+    }
+
+    #[test]
+    fn maximum_runtime() {
+        let mut board = heater_board(ActuatorControl {
+            min_on: 60,
+            max_runtime: Some(0),
+            ..Default::default()
+        });
+
+        board.set_activation(heater(true)).unwrap();
+        board.enforce_runtime().unwrap();
+        assert_eq!(board.state.get("heater"), Some(false));
+    }
+
+    #[test]
+    fn runtime_lockout() {
+        let mut board = heater_board(ActuatorControl {
+            max_runtime: Some(0),
+            lockout: Some(600),
+            ..Default::default()
+        });
+
+        board.set_activation(heater(true)).unwrap();
+        board.enforce_runtime().unwrap();
+        assert_eq!(board.state.get("heater"), Some(false));
+
+        //The next automatic cycle asks for it again right away
+        board.set_activation(heater(true)).unwrap();
+        assert_eq!(board.state.get("heater"), Some(false));
+
+        board
+            .locked_out
+            .insert("heater".to_string(), Instant::now());
+        board.enforce_runtime().unwrap();
+        assert!(board.locked_out.is_empty());
+        board.set_activation(heater(true)).unwrap();
+        assert_eq!(board.state.get("heater"), Some(true));
+    }
+
+    #[test]
+    fn pulse() {
+        let mut board = heater_board(ActuatorControl::default());
+        let options = PulseOptions {
+            duration: 60,
+            start: None,
+        };
+
+        board.command(heater(true), Some(options)).unwrap();
+        assert_eq!(board.state.get("heater"), Some(true));
+        assert_eq!(board.pulse_status()["heater"].remaining, 60);
+
+        //The automatic control can't cut a pulse short
+        board.set_activation(heater(false)).unwrap();
+        assert_eq!(board.state.get("heater"), Some(true));
+
+        board.pulses.get_mut("heater").unwrap().duration = Duration::ZERO;
+        board.update_pulses().unwrap();
+        assert_eq!(board.state.get("heater"), Some(false));
+        assert!(board.pulse_status().is_empty());
+    }
+
+    #[test]
+    fn restore() {
+        let mut board = heater_board(ActuatorControl::default());
+        board.state.set("irrigator", Some(false));
+        board
+            .set_auto_modes(ActivationState::from([("heater", false)]))
+            .unwrap();
+        let options = PulseOptions {
+            duration: 60,
+            start: None,
+        };
+        board.command(heater(true), Some(options)).unwrap();
+        let saved = serde_json::to_string(&board.snapshot()).unwrap();
+
+        let mut restarted = heater_board(ActuatorControl::default());
+        restarted.restore_from(serde_json::from_str(&saved).unwrap());
+        assert_eq!(restarted.state.get("heater"), Some(true));
+        assert_eq!(restarted.auto_modes.get("heater"), Some(false));
+        assert!(restarted.pulse_status()["heater"].remaining <= 60);
+    }
+
+    #[test]
+    fn variable_output() {
+        let mut board = heater_board(ActuatorControl::default());
+        board.actuators.push(ActuatorSpec {
+            output: OutputType::Variable,
+            ..ActuatorSpec::new(Actuators::Lighting, false)
+        });
+        board.state.set_level("lighting", Some(Level::Percent(0)));
+
+        let mut command = ActivationState::new();
+        command.set_level("lighting", Some(Level::Percent(40)));
+        command.set_level("heater", Some(Level::Percent(40)));
+        board.set_activation(command).unwrap();
+
+        let mut outputs = board.outputs();
+        outputs.sort();
+        assert_eq!(outputs, vec![(2, 40), (3, 100)]);
+        assert_eq!(board.state.level("heater"), Some(Level::Switch(true)));
+    }
+
+    #[test]
+    fn pulse_restores_level() {
+        let mut board = heater_board(ActuatorControl::default());
+        board.actuators.push(ActuatorSpec {
+            output: OutputType::Variable,
+            ..ActuatorSpec::new(Actuators::Lighting, false)
+        });
+        board.state.set_level("lighting", Some(Level::Percent(0)));
+        let mut dimmed = ActivationState::new();
+        dimmed.set_level("lighting", Some(Level::Percent(60)));
+        board.set_activation(dimmed).unwrap();
+
+        let options = PulseOptions {
+            duration: 90,
+            start: None,
+        };
+        let mut off = ActivationState::new();
+        off.set_level("lighting", Some(Level::Percent(0)));
+        board.command(off, Some(options)).unwrap();
+        assert_eq!(board.state.level("lighting"), Some(Level::Percent(0)));
+
+        board.pulses.get_mut("lighting").unwrap().duration = Duration::ZERO;
+        board.update_pulses().unwrap();
+        assert_eq!(board.state.level("lighting"), Some(Level::Percent(60)));
+    }
+}
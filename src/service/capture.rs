@@ -1,3 +1,5 @@
+use common::retention::CAPTURES_DIR;
+use common::settings::{Zone, zone_path};
 use image::imageops::FilterType;
 use image::{ImageFormat, ImageReader};
 use nokhwa::pixel_format::RgbFormat;
@@ -5,16 +7,15 @@ use nokhwa::utils::RequestedFormatType::AbsoluteHighestResolution;
 use nokhwa::utils::{CameraIndex, RequestedFormat};
 use nokhwa::{Camera, NokhwaError};
 use std::error::Error;
-use std::fs::{read, read_dir};
+use std::fs::{create_dir_all, read, read_dir};
 use std::io;
 use std::io::Cursor;
 use std::io::ErrorKind::NotFound;
 use std::thread::sleep;
 use std::time::{Duration, SystemTime};
 
-fn get_cam() -> Result<Camera, NokhwaError> {
-    // first camera in system
-    let index = CameraIndex::Index(0);
+fn get_cam(index: u32) -> Result<Camera, NokhwaError> {
+    let index = CameraIndex::Index(index);
     // request the absolute highest resolution CameraFormat that can be decoded to RGB.
     let requested = RequestedFormat::new::<RgbFormat>(AbsoluteHighestResolution);
     // make the camera
@@ -30,8 +31,8 @@ fn get_cam() -> Result<Camera, NokhwaError> {
     Ok(camera)
 }
 
-pub(super) fn save_frame() -> Result<String, Box<dyn Error>> {
-    let frame = get_cam()?.frame()?;
+pub(super) fn save_frame(zone: &Zone) -> Result<String, Box<dyn Error>> {
+    let frame = get_cam(zone.camera)?.frame()?;
 
     //Resize the frame into a more portable size
     let src_image = ImageReader::new(Cursor::new(frame.buffer()))
@@ -44,20 +45,26 @@ pub(super) fn save_frame() -> Result<String, Box<dyn Error>> {
         .as_secs()
         .to_string();
 
-    resized.save_with_format(
-        format!("/var/lib/cultiva/captures/{}.jpg", img_name),
-        ImageFormat::Jpeg,
-    )?;
+    let dir = zone_path(&zone.name, CAPTURES_DIR);
+    create_dir_all(&dir)?;
+    resized.save_with_format(format!("{}{}.jpg", dir, img_name), ImageFormat::Jpeg)?;
 
     println!("{}", t!("capture.success"));
     Ok(img_name)
 }
 
-pub(super) fn poll_cam() {
+//One picture per zone every few hours, zones that failed are retried sooner
+pub(super) fn poll_cam(zones: Vec<Zone>) {
     loop {
-        if let Err(e) = save_frame() {
-            eprintln!("{}. {}", t!("capture.failed", error = e), t!("retry"));
-            //Implement cleanup here (Not right now, I need the photos for the presentation)
+        let mut failed = false;
+        for zone in &zones {
+            if let Err(e) = save_frame(zone) {
+                eprintln!("{}. {}", t!("capture.failed", error = e), t!("retry"));
+                failed = true;
+            }
+        }
+        //Old captures are removed by the retention job
+        if failed {
             sleep(Duration::from_mins(1))
         } else {
             sleep(Duration::from_hours(3));
@@ -65,19 +72,20 @@ pub(super) fn poll_cam() {
     }
 }
 
-pub(super) fn get_image_buffer() -> Result<Vec<u8>, io::Error> {
+pub(super) fn get_image_buffer(zone: &Zone) -> Result<Vec<u8>, io::Error> {
+    let dir = zone_path(&zone.name, CAPTURES_DIR);
     //Save frame when image is requested
-    match save_frame() {
+    match save_frame(zone) {
         Ok(name) => {
             //If capture succeeds simply return the image
-            Ok(read(format!("/var/lib/cultiva/captures/{}.jpg", name))?)
+            Ok(read(format!("{}{}.jpg", dir, name))?)
         }
         Err(e) => {
             //If capture fails simply use the most recent one instead
             eprintln!("{}", t!("capture.failed", error = e));
 
             //This monstrosity returns the last created file
-            let paths = read_dir("/var/lib/cultiva/captures/")?;
+            let paths = read_dir(&dir)?;
 
             if let Some(last) = paths.max_by_key(|entry| {
                 if let Ok(val) = entry
@@ -100,6 +108,14 @@ pub(super) fn get_image_buffer() -> Result<Vec<u8>, io::Error> {
 #[cfg(test)]
 mod tests {
     use crate::service::capture::{poll_cam, save_frame};
+    use common::settings::{DEFAULT_ZONE, Zone};
+
+    fn zone() -> Zone {
+        Zone {
+            name: DEFAULT_ZONE.to_string(),
+            ..Default::default()
+        }
+    }
     use nokhwa::query;
     use nokhwa::utils::ApiBackend::Auto;
     use std::thread::sleep;
@@ -111,12 +127,12 @@ mod tests {
     }
     #[test]
     fn take_photo() {
-        save_frame().unwrap();
+        save_frame(&zone()).unwrap();
     }
     #[test]
     fn test_polling() {
         loop {
-            poll_cam();
+            poll_cam(vec![zone()]);
             println!("Photo taken!");
             sleep(Duration::from_secs(5));
         }
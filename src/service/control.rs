@@ -0,0 +1,327 @@
+use crate::service::supervision::{Threshold, VariableRange};
+use common::db_client::Reading;
+use common::settings::{ActuatorControl, ActuatorSpec, ControllerConfig, OutputType, Variable};
+use common::state_handling::{ActivationState, Level};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+//Control law of a single actuator
+pub(super) trait Controller: Send {
+    //Demand between 0 and 1, None keeps the actuator as it is
+    fn update(&mut self, value: f32, range: Option<&Threshold>, now: Instant) -> Option<f32>;
+}
+
+struct BangBang {
+    min: Option<f32>,
+    max: Option<f32>,
+    reverse: bool,
+    hysteresis: f32,
+}
+
+struct Pid {
+    setpoint: f32,
+    kp: f32,
+    ki: f32,
+    kd: f32,
+    reverse: bool,
+    integral: f32,
+    last: Option<(f32, Instant)>,
+}
+
+struct TimeProportional {
+    pid: Pid,
+    period: Duration,
+    cycle: Option<(f32, Instant)>,
+}
+
+impl Controller for BangBang {
+    fn update(&mut self, value: f32, range: Option<&Threshold>, _now: Instant) -> Option<f32> {
+        //Explicit limits take precedence over the ones given by the supervision
+        let min = self.min.or(range.map(|r| r.min))? - self.hysteresis;
+        let max = self.max.or(range.map(|r| r.max))? + self.hysteresis;
+
+        let (raise, lower) = if self.reverse { (0.0, 1.0) } else { (1.0, 0.0) };
+        if value < min {
+            Some(raise)
+        } else if value > max {
+            Some(lower)
+        } else {
+            None
+        }
+    }
+}
+
+impl Controller for Pid {
+    fn update(&mut self, value: f32, _range: Option<&Threshold>, now: Instant) -> Option<f32> {
+        let error = if self.reverse {
+            value - self.setpoint
+        } else {
+            self.setpoint - value
+        };
+
+        let (derivative, elapsed) = match self.last {
+            Some((last_error, last_time)) => {
+                let dt = (now - last_time).as_secs_f32().max(f32::EPSILON);
+                ((error - last_error) / dt, dt)
+            }
+            None => (0.0, 0.0),
+        };
+        self.last = Some((error, now));
+
+        let unclamped =
+            self.kp * error + self.ki * (self.integral + error * elapsed) + self.kd * derivative;
+        //Only integrate while the output isn't saturated, avoids windup
+        if (0.0..=1.0).contains(&unclamped) {
+            self.integral += error * elapsed;
+        }
+
+        Some(unclamped.clamp(0.0, 1.0))
+    }
+}
+
+impl Controller for TimeProportional {
+    fn update(&mut self, value: f32, range: Option<&Threshold>, now: Instant) -> Option<f32> {
+        let (duty, start) = match self.cycle {
+            Some((duty, start)) if now - start < self.period => (duty, start),
+            _ => {
+                let duty = self.pid.update(value, range, now)?;
+                self.cycle = Some((duty, now));
+                (duty, now)
+            }
+        };
+
+        if (now - start).as_secs_f32() < duty * self.period.as_secs_f32() {
+            Some(1.0)
+        } else {
+            Some(0.0)
+        }
+    }
+}
+
+fn build_controller(control: &ActuatorControl, reverse_default: bool) -> Box<dyn Controller> {
+    //Actuators that lower their variable are always reversed, the setting is for custom pairings
+    match control.controller.clone() {
+        ControllerConfig::BangBang { min, max, reverse } => Box::new(BangBang {
+            min,
+            max,
+            reverse: reverse || reverse_default,
+            hysteresis: control.hysteresis,
+        }),
+        ControllerConfig::Pid {
+            setpoint,
+            kp,
+            ki,
+            kd,
+            reverse,
+        } => Box::new(Pid {
+            setpoint,
+            kp,
+            ki,
+            kd,
+            reverse: reverse || reverse_default,
+            integral: 0.0,
+            last: None,
+        }),
+        ControllerConfig::TimeProportional {
+            setpoint,
+            kp,
+            ki,
+            kd,
+            reverse,
+            period,
+        } => Box::new(TimeProportional {
+            pid: Pid {
+                setpoint,
+                kp,
+                ki,
+                kd,
+                reverse: reverse || reverse_default,
+                integral: 0.0,
+                last: None,
+            },
+            period: Duration::from_secs(period.max(1)),
+            cycle: None,
+        }),
+    }
+}
+
+//Some actuators follow several variables (a fan cools and renews the air), one controller each
+type Targets = Vec<(Variable, Box<dyn Controller>)>;
+
+//Controllers of every actuator in automatic mode, they keep state between cycles
+pub(super) struct AutoControl {
+    controllers: HashMap<String, (OutputType, Targets)>,
+}
+
+//Several demands turn the actuator on if any of them asks for it and off only if all of them do,
+//otherwise it's kept as it is
+fn combine(demands: &[Option<f32>]) -> Option<f32> {
+    let max = demands.iter().flatten().copied().reduce(f32::max)?;
+    if max < 0.5 && demands.iter().any(|d| d.is_none()) {
+        return None;
+    }
+    Some(max)
+}
+
+impl AutoControl {
+    pub(super) fn new(
+        actuators: &[ActuatorSpec],
+        control: &HashMap<String, ActuatorControl>,
+    ) -> Self {
+        let mut controllers = HashMap::new();
+        for a in actuators {
+            let settings = control.get(&a.id).cloned().unwrap_or_default();
+            let defaults = a.kind.default_targets();
+
+            //The usual direction only applies when following one of the usual variables
+            let mut targets = match settings.variable {
+                Some(variable) => vec![(
+                    variable,
+                    defaults
+                        .iter()
+                        .any(|(v, reverse)| *v == variable && *reverse),
+                )],
+                None => defaults,
+            };
+            //Limits and setpoints belong to a single variable, only the ranges cover all of them
+            if !matches!(
+                settings.controller,
+                ControllerConfig::BangBang {
+                    min: None,
+                    max: None,
+                    ..
+                }
+            ) {
+                targets.truncate(1);
+            }
+
+            if !targets.is_empty() {
+                let built = targets
+                    .into_iter()
+                    .map(|(variable, reverse)| (variable, build_controller(&settings, reverse)))
+                    .collect();
+                controllers.insert(a.id.clone(), (a.output, built));
+            }
+        }
+
+        AutoControl { controllers }
+    }
+
+    //State requested by the controllers, only for actuators in automatic mode
+    pub(super) fn evaluate(
+        &mut self,
+        read: &Reading,
+        ranges: Option<&VariableRange>,
+        auto_modes: &ActivationState,
+    ) -> ActivationState {
+        let now = Instant::now();
+        let mut activate = ActivationState::new();
+        for (name, (output, targets)) in self.controllers.iter_mut() {
+            if !auto_modes.get(name).is_some_and(|x| x) {
+                continue;
+            }
+
+            let demands: Vec<Option<f32>> = targets
+                .iter_mut()
+                .filter_map(|(variable, controller)| {
+                    let value = read.get(*variable)?;
+                    let range = ranges.and_then(|r| r.get(*variable));
+                    Some(controller.update(value, range, now))
+                })
+                .collect();
+
+            //Relays turn on from half the demand, dimmable actuators follow it
+            if let Some(demand) = combine(&demands) {
+                let level = match output {
+                    OutputType::Binary => Level::Switch(demand >= 0.5),
+                    OutputType::Variable => Level::Percent((demand * 100.0).round() as u8),
+                };
+                activate.set_level(name, Some(level));
+            }
+        }
+
+        activate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::service::control::{BangBang, Controller, Pid, TimeProportional, combine};
+    use crate::service::supervision::Threshold;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn bang_bang_deadband() {
+        let mut shading = BangBang {
+            min: None,
+            max: None,
+            reverse: true,
+            hysteresis: 50.0,
+        };
+        let range = Threshold {
+            min: 1000.0,
+            max: 5000.0,
+        };
+        let now = Instant::now();
+
+        assert_eq!(shading.update(5020.0, Some(&range), now), None);
+        assert_eq!(shading.update(5100.0, Some(&range), now), Some(1.0));
+        assert_eq!(shading.update(900.0, Some(&range), now), Some(0.0));
+        assert_eq!(shading.update(900.0, None, now), None);
+    }
+
+    #[test]
+    fn pid_saturates() {
+        let mut heater = Pid {
+            setpoint: 22.0,
+            kp: 0.5,
+            ki: 0.01,
+            kd: 0.0,
+            reverse: false,
+            integral: 0.0,
+            last: None,
+        };
+        let start = Instant::now();
+
+        assert_eq!(heater.update(10.0, None, start), Some(1.0));
+        assert_eq!(
+            heater.update(30.0, None, start + Duration::from_secs(10)),
+            Some(0.0)
+        );
+        let near = heater
+            .update(21.5, None, start + Duration::from_secs(20))
+            .unwrap();
+        assert!(near > 0.0 && near < 1.0);
+    }
+
+    #[test]
+    fn time_proportional_duty() {
+        let mut heater = TimeProportional {
+            pid: Pid {
+                setpoint: 22.0,
+                kp: 0.25,
+                ki: 0.0,
+                kd: 0.0,
+                reverse: false,
+                integral: 0.0,
+                last: None,
+            },
+            period: Duration::from_secs(100),
+            cycle: None,
+        };
+        let start = Instant::now();
+
+        //Two degrees below the setpoint gives half of the period on
+        assert_eq!(heater.update(20.0, None, start), Some(1.0));
+        let late = start + Duration::from_secs(60);
+        assert_eq!(heater.update(20.0, None, late), Some(0.0));
+    }
+
+    #[test]
+    fn combined_demands() {
+        assert_eq!(combine(&[Some(1.0), None]), Some(1.0));
+        assert_eq!(combine(&[Some(0.0), None]), None);
+        assert_eq!(combine(&[Some(0.0), Some(0.2)]), Some(0.2));
+        assert_eq!(combine(&[None, None]), None);
+    }
+}
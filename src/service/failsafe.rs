@@ -0,0 +1,194 @@
+use crate::service::socket_io::emit_event;
+use chrono::Local;
+use common::db_client::{Reading, insert_event};
+use common::settings::Failsafe;
+use common::state_handling::ActivationState;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+//Something worth recording and reporting to the server
+pub(super) struct Event {
+    pub(super) kind: &'static str,
+    pub(super) detail: String,
+}
+
+//Keeps track of the sensor health and the hard limits, deciding which actuators must be forced
+pub(super) struct FailsafeMonitor {
+    config: Failsafe,
+    failed_polls: u32,
+    last_reading: Instant,
+    tripped: bool,
+    //One entry per hard limit, true while the reading is past it
+    exceeded: Vec<bool>,
+}
+
+impl FailsafeMonitor {
+    pub(super) fn new(config: Failsafe) -> Self {
+        FailsafeMonitor {
+            exceeded: vec![false; config.limits.len()],
+            config,
+            failed_polls: 0,
+            last_reading: Instant::now(),
+            tripped: false,
+        }
+    }
+
+    fn trip(&mut self, reason: String) -> Vec<Event> {
+        if self.tripped {
+            return Vec::new();
+        }
+        self.tripped = true;
+        vec![Event {
+            kind: "failsafe_tripped",
+            detail: t!("failsafe.tripped", reason = reason).to_string(),
+        }]
+    }
+
+    pub(super) fn failed_poll(&mut self) -> Vec<Event> {
+        self.failed_polls += 1;
+        if self.failed_polls < self.config.max_failed_polls {
+            return Vec::new();
+        }
+        self.trip(t!("failsafe.failed_polls", count = self.failed_polls).to_string())
+    }
+
+    pub(super) fn check_age(&mut self) -> Vec<Event> {
+        match self.config.max_reading_age {
+            Some(max) if self.last_reading.elapsed() >= Duration::from_secs(max) => {
+                self.trip(t!("failsafe.stale", seconds = max).to_string())
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub(super) fn reading(&mut self, read: &Reading) -> Vec<Event> {
+        let mut events = Vec::new();
+        self.failed_polls = 0;
+        self.last_reading = Instant::now();
+        if self.tripped {
+            self.tripped = false;
+            events.push(Event {
+                kind: "failsafe_recovered",
+                detail: t!("failsafe.recovered").to_string(),
+            });
+        }
+
+        for (limit, exceeded) in self.config.limits.iter().zip(self.exceeded.iter_mut()) {
+            //A missing value keeps the limit as it was
+            let Some(value) = read.get(limit.variable) else {
+                continue;
+            };
+            let past = limit.min.is_some_and(|min| value < min)
+                || limit.max.is_some_and(|max| value > max);
+            if past == *exceeded {
+                continue;
+            }
+
+            *exceeded = past;
+            let detail = if past {
+                t!(
+                    "failsafe.limit",
+                    variable = format!("{:?}", limit.variable),
+                    value = value,
+                    actuator = limit.actuator,
+                    state = limit.state
+                )
+            } else {
+                t!(
+                    "failsafe.limit_cleared",
+                    variable = format!("{:?}", limit.variable),
+                    actuator = limit.actuator
+                )
+            };
+            events.push(Event {
+                kind: if past {
+                    "limit_reached"
+                } else {
+                    "limit_cleared"
+                },
+                detail: detail.to_string(),
+            });
+        }
+
+        events
+    }
+
+    //States that override everything else, empty when all is fine
+    pub(super) fn interlocks(&self) -> ActivationState {
+        let mut forced = ActivationState::new();
+        if self.tripped {
+            for (name, state) in &self.config.safe_state {
+                forced.set(name, Some(*state));
+            }
+        }
+        for (limit, exceeded) in self.config.limits.iter().zip(&self.exceeded) {
+            if *exceeded {
+                forced.set(&limit.actuator, Some(limit.state));
+            }
+        }
+
+        forced
+    }
+}
+
+//Log the event, keep it in the database and let the server know
+pub(super) fn record(zone: &str, event: &Event) {
+    eprintln!("[{}] {}", zone, event.detail);
+    if let Err(e) = insert_event(zone, event.kind, &event.detail) {
+        eprintln!("{}", t!("failsafe.record_err", error = e));
+    }
+    emit_event(json!({
+        "zone": zone,
+        "kind": event.kind,
+        "detail": event.detail,
+        "time": Local::now().to_rfc3339()
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::service::failsafe::FailsafeMonitor;
+    use common::db_client::Reading;
+    use common::settings::{Failsafe, HardLimit, Variable};
+
+    #[test]
+    fn failed_polls() {
+        let mut monitor = FailsafeMonitor::new(Failsafe::default());
+
+        assert!(monitor.failed_poll().is_empty());
+        assert!(monitor.failed_poll().is_empty());
+        assert_eq!(monitor.failed_poll().len(), 1);
+        //Only reported once
+        assert!(monitor.failed_poll().is_empty());
+        assert_eq!(monitor.interlocks().get("heater"), Some(false));
+
+        let events = monitor.reading(&Reading::new());
+        assert_eq!(events[0].kind, "failsafe_recovered");
+        assert_eq!(monitor.interlocks().get("heater"), None);
+    }
+
+    #[test]
+    fn hard_limit() {
+        let mut monitor = FailsafeMonitor::new(Failsafe {
+            limits: vec![HardLimit {
+                actuator: "heater".to_string(),
+                variable: Variable::Temperature,
+                min: None,
+                max: Some(40.0),
+                state: false,
+            }],
+            ..Default::default()
+        });
+        let read = |temperature| Reading {
+            temperature: Some(temperature),
+            ..Default::default()
+        };
+
+        assert!(monitor.reading(&read(39.0)).is_empty());
+        assert_eq!(monitor.reading(&read(41.0))[0].kind, "limit_reached");
+        assert_eq!(monitor.interlocks().get("heater"), Some(false));
+        assert!(monitor.reading(&Reading::new()).is_empty());
+        assert_eq!(monitor.reading(&read(30.0))[0].kind, "limit_cleared");
+        assert_eq!(monitor.interlocks().get("heater"), None);
+    }
+}
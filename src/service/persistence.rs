@@ -0,0 +1,52 @@
+use chrono::{DateTime, Local};
+use common::settings::{write_zone_file, zone_path};
+use common::state_handling::{ActivationState, Level};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{exists, read_to_string};
+
+const STATE_FILE: &str = "/var/lib/cultiva/state.json";
+
+//What BoardControl needs to pick up where it was left after a restart
+#[derive(Serialize, Deserialize, Default)]
+pub(super) struct SavedState {
+    pub(super) state: ActivationState,
+    pub(super) auto_modes: ActivationState,
+    #[serde(default)]
+    pub(super) pulses: HashMap<String, SavedPulse>,
+}
+
+//Times are saved as wall clock, they don't mean anything across restarts otherwise
+#[derive(Serialize, Deserialize)]
+pub(super) struct SavedPulse {
+    pub(super) state: Level,
+    pub(super) start: DateTime<Local>,
+    pub(super) duration: u64,
+    pub(super) started: Option<DateTime<Local>>,
+    //Missing in files saved before it was kept
+    #[serde(default)]
+    pub(super) previous: Option<Level>,
+}
+
+pub(super) fn load_state(zone: &str) -> Result<Option<SavedState>, Box<dyn Error>> {
+    let path = zone_path(zone, STATE_FILE);
+    if !exists(&path)? {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::from_str(&read_to_string(&path)?)?))
+}
+
+pub(super) fn save_state(zone: &str, content: &str) {
+    if let Err(e) = write_zone_file(zone, STATE_FILE, content) {
+        eprintln!(
+            "{}",
+            t!(
+                "write_err",
+                filename = zone_path(zone, STATE_FILE),
+                error = e
+            )
+        );
+    }
+}
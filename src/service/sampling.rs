@@ -0,0 +1,264 @@
+use common::calibration::Calibration;
+use common::db_client::Reading;
+use common::settings::{Filter, Sampling, Variable};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+//Sample left out of a reading, kept to tell why
+pub(super) struct Rejection {
+    pub(super) variable: Variable,
+    pub(super) value: f32,
+    pub(super) reason: String,
+}
+
+//Combines several polls into a single reading
+pub(super) struct Sampler {
+    config: Sampling,
+    //Variables with a calibration profile, the only analog ones already in physical units
+    calibrated: HashSet<String>,
+    //Last value of each variable and when it was read, keyed by variable name
+    last: HashMap<&'static str, (f32, Instant)>,
+}
+
+fn median(sorted: &[f32]) -> f32 {
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+fn mean(values: &[f32]) -> f32 {
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+impl Sampler {
+    pub(super) fn new(config: Sampling, calibration: &HashMap<String, Calibration>) -> Self {
+        Sampler {
+            config,
+            calibrated: calibration.keys().cloned().collect(),
+            last: HashMap::new(),
+        }
+    }
+
+    pub(super) fn samples(&self) -> u32 {
+        self.config.samples.max(1)
+    }
+
+    pub(super) fn spacing(&self) -> Duration {
+        Duration::from_millis(self.config.spacing)
+    }
+
+    //Reason to reject the value, if any
+    fn check(&self, variable: Variable, value: f32, now: Instant) -> Option<String> {
+        let name = variable.name();
+        if !value.is_finite() {
+            return Some(t!("sampling.not_number").to_string());
+        }
+
+        //Raw counts of an uncalibrated probe can't be checked against the physical limits
+        let physical = !variable.analog() || self.calibrated.contains(name);
+        let bounds = self
+            .config
+            .bounds
+            .get(name)
+            .copied()
+            .or(physical.then(|| variable.physical_bounds()));
+        if let Some(bounds) = bounds
+            && (value < bounds.min || value > bounds.max)
+        {
+            return Some(
+                t!("sampling.out_of_bounds", min = bounds.min, max = bounds.max).to_string(),
+            );
+        }
+
+        //The time since the last reading keeps growing while values are rejected, so a real
+        //change is accepted eventually
+        if let Some(max) = self.config.max_rate.get(name)
+            && let Some((last, at)) = self.last.get(name)
+        {
+            let elapsed = (now - *at).as_secs_f32().max(f32::EPSILON);
+            let rate = (value - last).abs() / elapsed;
+            if rate > *max {
+                return Some(t!("sampling.spike", rate = rate, max = max).to_string());
+            }
+        }
+
+        None
+    }
+
+    pub(super) fn combine(
+        &mut self,
+        samples: &[Reading],
+        now: Instant,
+    ) -> (Reading, Vec<Rejection>) {
+        let mut read = Reading::new();
+        let mut rejected = Vec::new();
+        for variable in Variable::ALL {
+            let name = variable.name();
+            let mut accepted = Vec::new();
+            for sample in samples {
+                let Some(value) = sample.get(variable) else {
+                    continue;
+                };
+                match self.check(variable, value, now) {
+                    Some(reason) => rejected.push(Rejection {
+                        variable,
+                        value,
+                        reason,
+                    }),
+                    None => accepted.push((value, sample.raw.get(name).copied())),
+                }
+            }
+            if accepted.is_empty() {
+                continue;
+            }
+
+            let mut values: Vec<f32> = accepted.iter().map(|(value, _)| *value).collect();
+            values.sort_by(f32::total_cmp);
+            let value = match self.config.filter {
+                Filter::Median => median(&values),
+                Filter::TrimmedMean { trim } => {
+                    let cut = (values.len() as f32 * trim.clamp(0.0, 0.49)) as usize;
+                    mean(&values[cut..values.len() - cut])
+                }
+                Filter::Ema { alpha } => match self.last.get(name) {
+                    Some((last, _)) => alpha * mean(&values) + (1.0 - alpha) * last,
+                    None => mean(&values),
+                },
+            };
+
+            //Raw value of the sample closest to the result, to keep recalibration possible
+            if let Some(raw) = accepted
+                .iter()
+                .min_by(|a, b| (a.0 - value).abs().total_cmp(&(b.0 - value).abs()))
+                .and_then(|(_, raw)| *raw)
+            {
+                read.raw.insert(name.to_string(), raw);
+            }
+            read.set(variable, Some(value));
+            self.last.insert(name, (value, now));
+        }
+
+        (read, rejected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::service::sampling::Sampler;
+    use common::calibration::Calibration;
+    use common::db_client::Reading;
+    use common::settings::{Bounds, Filter, Sampling};
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    fn temperatures(values: &[f32]) -> Vec<Reading> {
+        values
+            .iter()
+            .map(|t| Reading {
+                temperature: Some(*t),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn median_ignores_glitches() {
+        let mut sampler = Sampler::new(Sampling::default(), &HashMap::new());
+        let (read, rejected) = sampler.combine(
+            &temperatures(&[21.0, 95.0, 21.4, 21.2, f32::NAN]),
+            Instant::now(),
+        );
+
+        assert_eq!(read.temperature, Some(21.2));
+        //Past the physical limits and not a number
+        assert_eq!(rejected.len(), 2);
+        assert_eq!(rejected[0].value, 95.0);
+    }
+
+    #[test]
+    fn spikes_rejected() {
+        let mut sampler = Sampler::new(
+            Sampling {
+                max_rate: HashMap::from([("temperature".to_string(), 0.1)]),
+                bounds: HashMap::from([(
+                    "soil_humidity".to_string(),
+                    Bounds {
+                        min: 0.0,
+                        max: 1023.0,
+                    },
+                )]),
+                ..Default::default()
+            },
+            &HashMap::new(),
+        );
+        let start = Instant::now();
+        sampler.combine(&temperatures(&[20.0]), start);
+
+        let later = start + Duration::from_secs(10);
+        let (read, rejected) = sampler.combine(&temperatures(&[20.5, 35.0]), later);
+        assert_eq!(read.temperature, Some(20.5));
+        assert_eq!(rejected.len(), 1);
+
+        //Uncalibrated values are fine inside the configured bounds
+        let soil = Reading {
+            soil_humidity: Some(600.0),
+            ..Default::default()
+        };
+        assert_eq!(sampler.combine(&[soil], later).0.soil_humidity, Some(600.0));
+    }
+
+    #[test]
+    fn filters() {
+        let mut trimmed = Sampler::new(
+            Sampling {
+                filter: Filter::TrimmedMean { trim: 0.25 },
+                ..Default::default()
+            },
+            &HashMap::new(),
+        );
+        let (read, _) = trimmed.combine(&temperatures(&[10.0, 20.0, 22.0, 30.0]), Instant::now());
+        assert_eq!(read.temperature, Some(21.0));
+
+        let mut ema = Sampler::new(
+            Sampling {
+                filter: Filter::Ema { alpha: 0.5 },
+                ..Default::default()
+            },
+            &HashMap::new(),
+        );
+        ema.combine(&temperatures(&[20.0]), Instant::now());
+        let (read, _) = ema.combine(&temperatures(&[23.0, 25.0]), Instant::now());
+        assert_eq!(read.temperature, Some(22.0));
+    }
+
+    #[test]
+    fn uncalibrated_probes() {
+        let soil = || {
+            vec![Reading {
+                soil_humidity: Some(154.0),
+                ..Default::default()
+            }]
+        };
+        //Raw counts of a probe without a profile are kept with the default settings
+        let mut sampler = Sampler::new(Sampling::default(), &HashMap::new());
+        let (read, rejected) = sampler.combine(&soil(), Instant::now());
+        assert_eq!(read.soil_humidity, Some(154.0));
+        assert!(rejected.is_empty());
+
+        //Once calibrated the value is a percentage, so the same number is a glitch
+        let calibration = HashMap::from([(
+            "soil_humidity".to_string(),
+            Calibration::TwoPoint {
+                dry: 800.0,
+                wet: 400.0,
+            },
+        )]);
+        let mut sampler = Sampler::new(Sampling::default(), &calibration);
+        let (read, rejected) = sampler.combine(&soil(), Instant::now());
+        assert_eq!(read.soil_humidity, None);
+        assert_eq!(rejected.len(), 1);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::service::board::BoardBackend;
+use chrono::{Local, Timelike};
+use common::db_client::Reading;
+use common::protocol::{FirmwareInfo, PROTOCOL_VERSION};
+use common::settings::{Actuators, IO, Sensors};
+use std::collections::HashMap;
+use std::error::Error;
+use std::f32::consts::PI;
+use std::time::Instant;
+
+//Greenhouse model used when there's no microcontroller, every variable drifts towards the
+//ambient conditions of the hour and reacts to the actuators that are currently on
+pub(super) struct SimulatedBoard {
+    io: IO,
+    outputs: HashMap<u8, u8>,
+    last_step: Instant,
+    noise_seed: u32,
+    temperature: f32,
+    air_humidity: f32,
+    soil_humidity: f32,
+    luminosity: f32,
+    co2: f32,
+    ph: f32,
+    ec: f32,
+    tank_level: f32,
+    soil_temperature: f32,
+    pressure: f32,
+    leaf_wetness: f32,
+}
+
+impl SimulatedBoard {
+    pub(super) fn new(io: IO) -> Self {
+        SimulatedBoard {
+            io,
+            outputs: HashMap::new(),
+            last_step: Instant::now(),
+            noise_seed: 0x2545F491,
+            temperature: 20.0,
+            air_humidity: 60.0,
+            soil_humidity: 45.0,
+            luminosity: 0.0,
+            co2: 420.0,
+            ph: 6.5,
+            ec: 1.8,
+            tank_level: 80.0,
+            soil_temperature: 18.0,
+            pressure: 1013.0,
+            leaf_wetness: 0.0,
+        }
+    }
+
+    //Combined output of every actuator of the kind, two heaters at full heat twice as fast
+    fn level(&self, kind: Actuators) -> f32 {
+        self.io
+            .actuators
+            .iter()
+            .filter(|a| a.kind == kind)
+            .map(|a| *self.outputs.get(&a.channel).unwrap_or(&0) as f32 / 100.0)
+            .sum()
+    }
+
+    //Xorshift, good enough to make the readings look like they come from real sensors
+    fn noise(&mut self, amplitude: f32) -> f32 {
+        self.noise_seed ^= self.noise_seed << 13;
+        self.noise_seed ^= self.noise_seed >> 17;
+        self.noise_seed ^= self.noise_seed << 5;
+        (self.noise_seed as f32 / u32::MAX as f32 - 0.5) * 2.0 * amplitude
+    }
+
+    //Advance the model by the given seconds at the given hour of the day
+    fn step(&mut self, seconds: f32, hour: f32) {
+        let ambient_temp = 20.0 + 6.0 * ((hour - 9.0) / 24.0 * 2.0 * PI).sin();
+        let daylight = ((hour - 6.0) / 12.0 * PI).sin().max(0.0) * 20000.0;
+
+        let heater = self.level(Actuators::Heater);
+        let irrigator = self.level(Actuators::Irrigator);
+        //Outside air replaces the inside one faster with the fan on
+        let exchange = 1.0 + 4.0 * self.level(Actuators::Fan);
+
+        self.temperature +=
+            ((ambient_temp - self.temperature) * 0.001 * exchange + heater * 0.01) * seconds;
+
+        //Soil dries faster when it's hot
+        let evaporation = 0.0005 * (1.0 + (self.temperature - 20.0).max(0.0) / 10.0);
+        self.soil_humidity += (irrigator * 0.05 - evaporation) * seconds;
+        self.soil_humidity = self.soil_humidity.clamp(0.0, 100.0);
+
+        let ambient_humidity = 60.0 - (self.temperature - 20.0) * 2.0;
+        let humidifying = irrigator * 0.01 + self.level(Actuators::Humidifier) * 0.03
+            - self.level(Actuators::Dehumidifier) * 0.03;
+        self.air_humidity +=
+            ((ambient_humidity - self.air_humidity) * 0.002 * exchange + humidifying) * seconds;
+        self.air_humidity = self.air_humidity.clamp(0.0, 100.0);
+
+        self.luminosity = daylight * (1.0 - 0.6 * self.level(Actuators::Shading).min(1.0))
+            + self.level(Actuators::Lighting) * 8000.0;
+
+        //Plants consume CO2 while there's light
+        let co2_target = 420.0 - self.luminosity / 200.0;
+        self.co2 += ((co2_target - self.co2) * 0.001 * exchange
+            + self.level(Actuators::Co2Injector) * 2.0)
+            * seconds;
+
+        //Nutrients acidify the solution a little
+        let dosing = self.level(Actuators::PhUpPump) * 0.002
+            - self.level(Actuators::PhDownPump) * 0.002
+            - self.level(Actuators::NutrientPump) * 0.0005;
+        self.ph += ((6.5 - self.ph) * 0.0001 + dosing) * seconds;
+        self.ph = self.ph.clamp(0.0, 14.0);
+        self.ec +=
+            ((1.8 - self.ec) * 0.0001 + self.level(Actuators::NutrientPump) * 0.002) * seconds;
+
+        //The irrigator draws from the reservoir, nothing refills it
+        self.tank_level = (self.tank_level - irrigator * 0.02 * seconds).max(0.0);
+        self.soil_temperature +=
+            (self.temperature - 2.0 - self.soil_temperature) * 0.0002 * seconds;
+        self.pressure = 1013.0 + 3.0 * (hour / 24.0 * 2.0 * PI).cos();
+
+        //Leaves stay wet while the air is close to saturation
+        let wetting = if self.air_humidity > 85.0 {
+            0.05
+        } else {
+            -0.02
+        };
+        self.leaf_wetness = (self.leaf_wetness + wetting * seconds).clamp(0.0, 100.0);
+    }
+}
+
+impl BoardBackend for SimulatedBoard {
+    fn identify(&mut self) -> Result<Option<FirmwareInfo>, Box<dyn Error>> {
+        Ok(Some(FirmwareInfo {
+            version: "simulated".to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            flags: self.io.clone().into(),
+        }))
+    }
+
+    fn send_outputs(&mut self, outputs: &[(u8, u8)]) -> Result<(), Box<dyn Error>> {
+        for (channel, value) in outputs {
+            self.outputs.insert(*channel, *value);
+        }
+        println!(
+            "{}",
+            t!("serial.command.sent", command = format!("{:?}", outputs))
+        );
+
+        Ok(())
+    }
+
+    fn poll_sensors(&mut self) -> Result<Reading, Box<dyn Error>> {
+        let now = Local::now();
+        let hour = now.hour() as f32 + now.minute() as f32 / 60.0;
+        self.step(self.last_step.elapsed().as_secs_f32(), hour);
+        self.last_step = Instant::now();
+
+        let mut read = Reading::new();
+        for s in self.io.sensors.clone() {
+            match s {
+                Sensors::DHT11 => {
+                    read.temperature = Some(self.temperature + self.noise(0.2));
+                    read.air_humidity = Some(self.air_humidity + self.noise(1.0));
+                }
+                Sensors::Thermometer => {
+                    read.temperature = Some(self.temperature + self.noise(0.1));
+                }
+                Sensors::Hygrometer => {
+                    read.air_humidity = Some(self.air_humidity + self.noise(0.5));
+                }
+                Sensors::SoilHygrometer => {
+                    read.soil_humidity = Some(self.soil_humidity + self.noise(0.5));
+                }
+                Sensors::Luminometer => {
+                    read.luminosity = Some((self.luminosity + self.noise(50.0)).max(0.0));
+                }
+                Sensors::Co2 => read.air_quality = Some(self.co2 + self.noise(5.0)),
+                Sensors::PH => read.ph = Some(self.ph + self.noise(0.05)),
+                Sensors::Ec => read.ec = Some(self.ec + self.noise(0.02)),
+                Sensors::TankLevel => read.tank_level = Some(self.tank_level),
+                Sensors::SoilThermometer => {
+                    read.soil_temperature = Some(self.soil_temperature + self.noise(0.1))
+                }
+                Sensors::Barometer => read.pressure = Some(self.pressure + self.noise(0.3)),
+                Sensors::LeafWetness => read.leaf_wetness = Some(self.leaf_wetness),
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::service::board::BoardBackend;
+    use crate::service::simulated::SimulatedBoard;
+    use common::settings::{ActuatorSpec, Actuators, IO};
+
+    fn greenhouse() -> SimulatedBoard {
+        SimulatedBoard::new(IO {
+            sensors: Vec::new(),
+            actuators: vec![
+                ActuatorSpec::new(Actuators::Heater, false),
+                ActuatorSpec::new(Actuators::Irrigator, false),
+            ],
+        })
+    }
+
+    #[test]
+    fn heater_raises_temperature() {
+        let mut board = greenhouse();
+        board.step(600.0, 3.0);
+        let unheated = board.temperature;
+
+        board
+            .send_outputs(&[(Actuators::Heater.default_channel(), 100)])
+            .unwrap();
+        board.step(600.0, 3.0);
+        assert!(board.temperature > unheated);
+    }
+
+    #[test]
+    fn irrigation_raises_soil_humidity() {
+        let mut board = greenhouse();
+        board.step(600.0, 12.0);
+        let dry = board.soil_humidity;
+        assert!(dry < 45.0);
+
+        board
+            .send_outputs(&[(Actuators::Irrigator.default_channel(), 100)])
+            .unwrap();
+        board.step(60.0, 12.0);
+        assert!(board.soil_humidity > dry);
+    }
+}
@@ -2,9 +2,13 @@ use common::credentials::get_jwt;
 use rust_socketio::client::Client;
 use rust_socketio::{Payload, RawClient};
 use serde_json::{Value, json};
+use std::sync::OnceLock;
 use std::thread::sleep;
 use std::time::Duration;
 
+//Connected client, used to report events that don't answer any request
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
 fn payload_to_string(payload: Payload) -> String {
     if let Payload::Text(content) = payload {
         let mut all = "".to_string();
@@ -105,6 +109,21 @@ pub(super) fn report_result(socket: RawClient, response_id: &str, result: bool,
     }
 }
 
+pub(super) fn register_client(client: Client) {
+    //The client reconnects by itself, so the first one is kept
+    let _ = CLIENT.set(client);
+}
+
+pub(super) fn emit_event(data: Value) {
+    let Some(client) = CLIENT.get() else {
+        eprintln!("{}", t!("socket_io.event_offline"));
+        return;
+    };
+    if let Err(e) = client.emit("event", data) {
+        eprintln!("{}", t!("socket_io.event_err", error = e));
+    }
+}
+
 pub(super) fn test_connection(client: Client) {
     loop {
         sleep(Duration::from_mins(3));
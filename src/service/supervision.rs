@@ -2,12 +2,13 @@ use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
 use common::db_client::Reading;
 use common::rest_client::{Output, get_evaluation};
+use common::settings::{Variable, write_zone_file, zone_path};
 use common::state_handling::ActivationState;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use std::collections::HashMap;
 use std::error::Error;
-use std::fs::{read_to_string, write};
+use std::fs::read_to_string;
 use std::io;
 use std::io::ErrorKind::Other;
 
@@ -24,6 +25,37 @@ pub(super) struct VariableRange {
     pub(super) air_humidity: Threshold,
     pub(super) luminosity: Threshold,
     pub(super) co2: Threshold,
+    //Only sent for installations with the matching sensors
+    #[serde(default)]
+    pub(super) ph: Option<Threshold>,
+    #[serde(default)]
+    pub(super) ec: Option<Threshold>,
+    #[serde(default)]
+    pub(super) tank_level: Option<Threshold>,
+    #[serde(default)]
+    pub(super) soil_temperature: Option<Threshold>,
+    #[serde(default)]
+    pub(super) pressure: Option<Threshold>,
+    #[serde(default)]
+    pub(super) leaf_wetness: Option<Threshold>,
+}
+
+impl VariableRange {
+    pub(super) fn get(&self, variable: Variable) -> Option<&Threshold> {
+        match variable {
+            Variable::Temperature => Some(&self.temperature),
+            Variable::AirHumidity => Some(&self.air_humidity),
+            Variable::SoilHumidity => Some(&self.soil_humidity),
+            Variable::Luminosity => Some(&self.luminosity),
+            Variable::AirQuality => Some(&self.co2),
+            Variable::Ph => self.ph.as_ref(),
+            Variable::Ec => self.ec.as_ref(),
+            Variable::TankLevel => self.tank_level.as_ref(),
+            Variable::SoilTemperature => self.soil_temperature.as_ref(),
+            Variable::Pressure => self.pressure.as_ref(),
+            Variable::LeafWetness => self.leaf_wetness.as_ref(),
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -35,7 +67,11 @@ struct SupervisionResponse {
     ranges: VariableRange,
 }
 
+const ASSESSMENT_FILE: &str = "/var/lib/cultiva/assessment.json";
+const RANGES_FILE: &str = "/var/lib/cultiva/ranges.toml";
+
 pub(super) async fn evaluate(
+    zone: &str,
     readings: Vec<Reading>,
     context: HashMap<String, String>,
     activation: ActivationState,
@@ -47,18 +83,18 @@ pub(super) async fn evaluate(
     if eval.status().is_success() {
         let data = eval.json::<SupervisionResponse>().await?;
 
-        let file = "/var/lib/cultiva/assessment.json";
         let content =
             json!({"health": data.health, "message": data.message, "advice": data.advice });
-        if let Err(e) = write(file, content.to_string()) {
+        if let Err(e) = write_zone_file(zone, ASSESSMENT_FILE, content.to_string()) {
+            let file = zone_path(zone, ASSESSMENT_FILE);
             eprintln!("{}", t!("write_err", filename = file, error = e));
         };
 
-        let ranges = "/var/lib/cultiva/ranges.toml";
         if let Ok(content) = toml::to_string(&data.ranges)
-            && let Err(e) = write(ranges, content)
+            && let Err(e) = write_zone_file(zone, RANGES_FILE, content)
         {
-            eprintln!("{}", t!("write_err", filename = ranges, error = e));
+            let file = zone_path(zone, RANGES_FILE);
+            eprintln!("{}", t!("write_err", filename = file, error = e));
         }
 
         return Ok(data.command);
@@ -72,14 +108,14 @@ pub(super) async fn evaluate(
     )))
 }
 
-pub(super) fn get_assessment() -> Result<Value, Box<dyn Error>> {
-    let content = read_to_string("/var/lib/cultiva/assessment.json")?;
+pub(super) fn get_assessment(zone: &str) -> Result<Value, Box<dyn Error>> {
+    let content = read_to_string(zone_path(zone, ASSESSMENT_FILE))?;
 
     Ok(serde_json::from_str(&content)?)
 }
 
-pub(super) fn get_ranges() -> Result<VariableRange, Box<dyn Error>> {
-    let content = read_to_string("/var/lib/cultiva/ranges.toml")?;
+pub(super) fn get_ranges(zone: &str) -> Result<VariableRange, Box<dyn Error>> {
+    let content = read_to_string(zone_path(zone, RANGES_FILE))?;
     let serialize = toml::from_str::<VariableRange>(&content)?;
 
     Ok(serialize)